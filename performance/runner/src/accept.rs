@@ -0,0 +1,179 @@
+use crate::calculate::{Calculation, MetricKey};
+use crate::exceptions::{CalculateError, IOError};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// A regression we've knowingly accepted, like a correctness fix that costs
+// time. Its calculations are still reported, but don't fail the run. An
+// acceptance lasts through its `until` date, or forever without one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acceptance {
+    pub key: MetricKey,
+    pub until: Option<NaiveDate>,
+    pub reason: Option<String>,
+}
+
+// One entry of an acceptance file:
+//
+// [01_2000_simple_models.parse]
+// until = "2021-09-01"
+// reason = "node selection got slower to fix #1234"
+//
+// where the table names are the project and then the metric.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AcceptanceEntry {
+    until: Option<NaiveDate>,
+    reason: Option<String>,
+}
+
+// Parses `<project>/<metric>`, as given to `--accept`. These never expire.
+impl FromStr for Acceptance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('/') {
+            Some((project, metric)) if !project.is_empty() && !metric.is_empty() => {
+                Ok(Acceptance {
+                    key: MetricKey::new(project, metric),
+                    until: None,
+                    reason: None,
+                })
+            }
+            _ => Err(format!(
+                "invalid acceptance '{}'. expected <project>/<metric>",
+                s
+            )),
+        }
+    }
+}
+
+impl Acceptance {
+    pub fn expired(&self, today: NaiveDate) -> bool {
+        self.until.is_some_and(|until| today > until)
+    }
+}
+
+// Reads every acceptance in a toml file.
+pub fn from_toml_file(path: &Path) -> Result<Vec<Acceptance>, CalculateError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| IOError::BadFileContentsErr(path.to_path_buf(), Some(e)))?;
+
+    let entries: HashMap<String, HashMap<String, AcceptanceEntry>> = toml::from_str(&contents)
+        .map_err(|e| CalculateError::BadTOMLErr(path.to_path_buf(), Some(e)))?;
+
+    let mut acceptances: Vec<Acceptance> = entries
+        .into_iter()
+        .flat_map(|(project, metrics)| {
+            metrics.into_iter().map(move |(metric, entry)| Acceptance {
+                key: MetricKey::new(&project, &metric),
+                until: entry.until,
+                reason: entry.reason,
+            })
+        })
+        .collect();
+    // so they're reported in the same order every run
+    acceptances.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(acceptances)
+}
+
+// Marks the regressions covered by an unexpired acceptance as accepted.
+// Returns the acceptances that covered a regression, and the ones that have
+// expired so they can be cleaned up.
+pub fn apply<'a>(
+    calcs: &mut [Calculation],
+    acceptances: &'a [Acceptance],
+    today: NaiveDate,
+) -> (Vec<&'a Acceptance>, Vec<&'a Acceptance>) {
+    let (expired, current): (Vec<&Acceptance>, Vec<&Acceptance>) =
+        acceptances.iter().partition(|a| a.expired(today));
+
+    let applied = current
+        .into_iter()
+        .filter(|a| {
+            let mut covered = false;
+            for c in calcs.iter_mut() {
+                if c.regression && MetricKey::from_calculation(&c.metric) == a.key {
+                    c.accepted = true;
+                    covered = true;
+                }
+            }
+            covered
+        })
+        .collect();
+
+    (applied, expired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measure::TmpDir;
+
+    #[test]
+    fn parses_acceptances() {
+        let a: Acceptance = "01_2000_simple_models/parse".parse().unwrap();
+        assert_eq!(a.key, MetricKey::new("01_2000_simple_models", "parse"));
+        assert_eq!(a.until, None);
+        assert!("parse".parse::<Acceptance>().is_err());
+        assert!("project/".parse::<Acceptance>().is_err());
+
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let path = tmp.path().join("parses_acceptances.toml");
+        fs::write(
+            &path,
+            "[b.parse]\nuntil = \"2021-09-01\"\nreason = \"a fix\"\n\n[a.parse]\n",
+        )
+        .unwrap();
+        let from_file = from_toml_file(&path);
+
+        let from_file = from_file.unwrap();
+        assert_eq!(from_file[0].key, MetricKey::new("a", "parse"));
+        assert_eq!(
+            from_file[1],
+            Acceptance {
+                key: MetricKey::new("b", "parse"),
+                until: Some(NaiveDate::from_ymd(2021, 9, 1)),
+                reason: Some("a fix".to_owned()),
+            }
+        );
+    }
+
+    #[test]
+    fn accepts_regressions_until_they_expire() {
+        let calc = |metric: &str, regression| Calculation {
+            regression,
+            ..Calculation::for_test(metric, 1.0, 1.1)
+        };
+        let mut calcs = vec![
+            calc("median_parse_a.json", true),
+            calc("stddev_parse_a.json", false),
+            calc("median_parse_b.json", true),
+        ];
+        let until = |project: &str, until| Acceptance {
+            key: MetricKey::new(project, "parse"),
+            until,
+            reason: None,
+        };
+        let acceptances = vec![
+            until("a", Some(NaiveDate::from_ymd(2021, 8, 1))),
+            until("b", Some(NaiveDate::from_ymd(2021, 7, 1))),
+            until("c", None),
+        ];
+
+        let (applied, expired) = apply(&mut calcs, &acceptances, NaiveDate::from_ymd(2021, 8, 1));
+
+        assert_eq!(applied, vec![&acceptances[0]]);
+        assert_eq!(expired, vec![&acceptances[1]]);
+        assert_eq!(
+            calcs.iter().map(|c| c.accepted).collect::<Vec<bool>>(),
+            vec![true, false, false]
+        );
+        assert!(!calcs[0].gates());
+        assert!(calcs[2].gates());
+    }
+}
@@ -1,15 +1,23 @@
 use crate::exceptions::{CalculateError, IOError};
+use crate::filter::Filter;
+use crate::stats;
 use chrono::prelude::*;
+use flate2::read::GzDecoder;
 use itertools::Itertools;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::fs::DirEntry;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-// This type exactly matches the type of array elements
-// from hyperfine's output. Deriving `Serialize` and `Deserialize`
-// gives us read and write capabilities via json_serde.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+// This type matches the type of array elements from hyperfine's
+// output, plus the tail percentiles we compute from the times.
+// Deriving `Serialize` and `Deserialize` gives us read and write
+// capabilities via json_serde.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct Measurement {
     pub command: String,
     pub mean: f64,
@@ -20,12 +28,92 @@ pub struct Measurement {
     pub min: f64,
     pub max: f64,
     pub times: Vec<f64>,
+    // hyperfine doesn't report these, and results written before they were
+    // added don't have them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p95: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p99: Option<f64>,
+    // the exit code of each timed run, as hyperfine reports them when
+    // failures are ignored. `None` for a run that was killed by a signal.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exit_codes: Vec<Option<i32>>,
+}
+
+impl Measurement {
+    // Corrupt output can hold values that deserialize fine but would quietly
+    // make every comparison false. Returns the first non-finite field.
+    fn validate(&self, path: &Path) -> Result<(), CalculateError> {
+        [
+            ("mean", self.mean),
+            ("stddev", self.stddev),
+            ("median", self.median),
+            ("user", self.user),
+            ("system", self.system),
+            ("min", self.min),
+            ("max", self.max),
+        ]
+        .iter()
+        .copied()
+        .chain(self.p95.map(|p| ("p95", p)))
+        .chain(self.p99.map(|p| ("p99", p)))
+        .chain(self.times.iter().map(|t| ("times", *t)))
+        .find(|(_, v)| !v.is_finite())
+        .map_or(Ok(()), |(name, v)| {
+            Err(CalculateError::NonFiniteErr(
+                path.to_path_buf(),
+                name.to_owned(),
+                v,
+            ))
+        })
+    }
+
+    // Whether any timed run of the command failed, making the times
+    // meaningless.
+    pub fn failed(&self) -> bool {
+        self.exit_codes.iter().any(|code| *code != Some(0))
+    }
+
+    // Fills in the tail percentiles from the times. Without any times
+    // they're left unset.
+    pub fn with_percentiles(self) -> Measurement {
+        Measurement {
+            p95: stats::percentile(&self.times, 0.95),
+            p99: stats::percentile(&self.times, 0.99),
+            ..self
+        }
+    }
+
+    // Recomputes the summary statistics from the times left after
+    // trimming `fraction` off of each end. Measurements without enough
+    // times to summarize are left as hyperfine reported them.
+    fn trimmed(&self, fraction: f64) -> Measurement {
+        let times = stats::trim(&self.times, fraction);
+        match (
+            stats::mean(&times),
+            stats::variance(&times),
+            stats::median(&times),
+        ) {
+            (Some(mean), Some(variance), Some(median)) if fraction > 0.0 => Measurement {
+                mean,
+                stddev: variance.sqrt(),
+                median,
+                // trim leaves the times sorted
+                min: times[0],
+                max: times[times.len() - 1],
+                times,
+                ..self.clone()
+            }
+            .with_percentiles(),
+            _ => self.clone(),
+        }
+    }
 }
 
 // This type exactly matches the type of hyperfine's output.
 // Deriving `Serialize` and `Deserialize` gives us read and
 // write capabilities via json_serde.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
 pub struct Measurements {
     pub results: Vec<Measurement>,
 }
@@ -35,9 +123,42 @@ pub struct Measurements {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Data {
     pub threshold: f64,
-    pub difference: f64,
+    // dev over baseline. `None` when the baseline is zero, since a zero
+    // baseline has no meaningful ratio.
+    pub difference: Option<f64>,
+    pub pct_change: Option<f64>,
+    pub p_value: Option<f64>,
+    // Mann-Whitney U of dev against baseline, when that test decided it
+    pub u_statistic: Option<f64>,
+    // significance level the p-value was held to, after any correction
+    pub alpha: Option<f64>,
+    // smallest absolute change, in seconds, that can count as a regression
+    #[serde(default)]
+    pub min_abs_delta: f64,
+    // confidence interval for the baseline mean
+    pub ci_low: Option<f64>,
+    pub ci_high: Option<f64>,
+    // bootstrap confidence interval for dev's mean minus baseline's
+    pub diff_ci_low: Option<f64>,
+    pub diff_ci_high: Option<f64>,
     pub baseline: f64,
     pub dev: f64,
+    // dev's coefficient of variation, stddev / mean, for telling results
+    // that are reliably unchanged from ones within the noise. `None` with
+    // a zero mean.
+    pub cv: Option<f64>,
+}
+
+// Which rule a calculation's difference was computed with. A baseline
+// with no variation at all would make any noise in dev look infinitely
+// worse, so those are compared against a floor relative to the baseline
+// mean instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    #[default]
+    Ratio,
+    StddevFloor,
 }
 
 // The full output from a comparison between runs on the baseline
@@ -46,107 +167,1056 @@ pub struct Data {
 pub struct Calculation {
     pub metric: String,
     pub regression: bool,
+    pub improvement: bool,
     pub ts: DateTime<Utc>,
+    pub rule: Rule,
+    // which way the compared values get better, so a change can be told
+    // apart from a slowdown. Stddevs are always lower-is-better.
+    #[serde(default)]
+    pub direction: Direction,
+    // too noisy to trust, so not counted toward the exit code
+    pub noisy: bool,
+    // a measured command failed, so likewise not counted toward the exit code
+    #[serde(default)]
+    pub failed: bool,
+    // its verdict has flipped too often in the run history to be trusted,
+    // so likewise not counted
+    #[serde(default)]
+    pub quarantined: bool,
+    // a knowingly accepted regression, so likewise not counted
+    #[serde(default)]
+    pub accepted: bool,
+    // a side spent so much of its cpu time in the kernel that the machine was
+    // probably busy with something else, so likewise not counted
+    #[serde(default)]
+    pub suspect: bool,
+    // the commit dev was measured on, when it's known
+    pub commit: Option<String>,
     pub data: Data,
+    // missing from calculations made before it was recorded
+    #[serde(default)]
+    pub observed: Option<Observed>,
+}
+
+// More of what the pair's measurements saw than the statistic they were
+// compared by, so a regression can be triaged without opening the results
+// files. Dev's fastest and slowest runs show whether it was slow throughout
+// or had outliers, and the baseline's cpu times are what dev's are compared
+// against by eye.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Observed {
+    pub dev_min: f64,
+    pub dev_max: f64,
+    pub baseline_user: f64,
+    pub baseline_system: f64,
+}
+
+// A ratio calculation from `baseline` to `dev` at a fixed time, judged by
+// the default 5% threshold with nothing else flagged, for the tests of the
+// modules that consume calculations to adjust with struct update syntax.
+#[cfg(test)]
+impl Calculation {
+    pub fn for_test(metric: &str, baseline: f64, dev: f64) -> Self {
+        let difference = stats::safe_ratio(dev, baseline);
+        Calculation {
+            metric: metric.to_owned(),
+            regression: is_regression(difference, dev, baseline, 1.05),
+            improvement: difference.is_some_and(|d| is_improvement(d, 1.05)),
+            ts: Utc.ymd(2021, 7, 29).and_hms(12, 0, 0),
+            rule: Rule::Ratio,
+            direction: Direction::LowerIsBetter,
+            noisy: false,
+            failed: false,
+            quarantined: false,
+            accepted: false,
+            suspect: false,
+            commit: None,
+            data: Data {
+                threshold: 1.05,
+                difference,
+                pct_change: pct_change(dev, baseline),
+                p_value: None,
+                u_statistic: None,
+                alpha: None,
+                min_abs_delta: 0.0,
+                ci_low: None,
+                ci_high: None,
+                diff_ci_low: None,
+                diff_ci_high: None,
+                baseline,
+                dev,
+                cv: None,
+            },
+            observed: None,
+        }
+    }
+}
+
+impl Calculation {
+    // Whether this is a regression that counts toward failing the run.
+    pub fn gates(&self) -> bool {
+        self.regression && self.skip_reason().is_none()
+    }
+
+    // Why a regression doesn't count toward failing the run, or `None` when
+    // it does or there's no regression to skip.
+    pub fn skip_reason(&self) -> Option<&'static str> {
+        if !self.regression {
+            return None;
+        }
+        [
+            (self.accepted, "accepted"),
+            (self.quarantined, "quarantined"),
+            (self.failed, "failed"),
+            (self.suspect, "suspect"),
+            (self.noisy, "noisy"),
+        ]
+        .iter()
+        .find(|(flagged, _)| *flagged)
+        .map(|(_, reason)| *reason)
+    }
+}
+
+// Everything found by comparing a results directory. Calculations are made
+// for every project-metric pair measured on both branches. Pairs with a
+// baseline measurement but no dev measurement are collected as missing,
+// since that usually means the benchmark crashed before writing its results.
+// Pairs with only a dev measurement are new, and need a baseline measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub calculations: Vec<Calculation>,
+    pub missing: Vec<MetricKey>,
+    pub new: Vec<MetricKey>,
 }
 
 // A type to describe which measurement we are working with. This
 // information is parsed from the filename of hyperfine's output.
+// Everything is borrowed from the filenames and measurements that were read in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementGroup<'a> {
+    pub version: &'a str,
+    pub run: &'a str,
+    pub key: MetricKey,
+    pub measurement: &'a Measurement,
+}
+
+// The thresholds a dev measurement is held to. Each threshold is the
+// maximum allowed ratio of dev to baseline, so 1.05 allows dev to be
+// up to 5% slower than baseline before it is flagged as a regression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub median: f64,
+    pub stddev: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            median: 1.05, // 5% regression threshold
+            stddev: 1.20, // 20% regression threshold
+        }
+    }
+}
+
+impl Thresholds {
+    // Thresholds are ratios so anything that isn't a positive number
+    // can't be meaningfully compared against.
+    fn validate(&self) -> Result<(), CalculateError> {
+        [("median", self.median), ("stddev", self.stddev)]
+            .iter()
+            .find(|(_, t)| t.is_nan() || *t <= 0.0)
+            .map_or(Ok(()), |(name, t)| {
+                Err(CalculateError::BadThresholdErr((*name).to_owned(), *t))
+            })
+    }
+}
+
+// Overrides for a single project-metric pair. Any threshold left out
+// falls back to the global value.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ThresholdOverride {
+    pub median: Option<f64>,
+    pub stddev: Option<f64>,
+}
+
+// The global thresholds plus any per-project, per-metric overrides. The
+// overrides are read from a toml file of the form:
+//
+// [01_2000_simple_models.parse]
+// median = 1.10
+//
+// where the table names are the project and then the metric.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThresholdConfig {
+    pub global: Thresholds,
+    pub overrides: HashMap<String, HashMap<String, ThresholdOverride>>,
+}
+
+impl ThresholdConfig {
+    // Reads the overrides from a toml file and pairs them with the global thresholds.
+    pub fn from_toml_file(path: &Path, global: Thresholds) -> Result<Self, CalculateError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| IOError::BadFileContentsErr(path.to_path_buf(), Some(e)))?;
+
+        let overrides = toml::from_str(&contents)
+            .map_err(|e| CalculateError::BadTOMLErr(path.to_path_buf(), Some(e)))?;
+
+        Ok(ThresholdConfig { global, overrides })
+    }
+
+    // Returns the thresholds to use for a given project-metric pair.
+    pub fn resolve(&self, key: &MetricKey) -> Thresholds {
+        self.overrides
+            .get(&key.project)
+            .and_then(|metrics| metrics.get(&key.metric))
+            .map_or(self.global, |o| Thresholds {
+                median: o.median.unwrap_or(self.global.median),
+                stddev: o.stddev.unwrap_or(self.global.stddev),
+            })
+    }
+
+    // Validates the global thresholds and every resolvable override.
+    fn validate(&self) -> Result<(), CalculateError> {
+        self.global.validate()?;
+        self.overrides
+            .iter()
+            .flat_map(|(project, metrics)| {
+                metrics
+                    .keys()
+                    .map(move |metric| self.resolve(&MetricKey::new(project, metric)))
+            })
+            .try_for_each(|t| t.validate())
+    }
+}
+
+// The statistic used to summarize each measurement when comparing
+// dev to baseline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Statistic {
+    Mean,
+    #[default]
+    Median,
+    // the fastest run, the most repeatable for warm-cache commands
+    Min,
+    P95,
+    P99,
+}
+
+impl FromStr for Statistic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Statistic::Mean),
+            "median" => Ok(Statistic::Median),
+            "min" => Ok(Statistic::Min),
+            "p95" => Ok(Statistic::P95),
+            "p99" => Ok(Statistic::P99),
+            _ => Err(format!(
+                "unknown statistic '{}'. expected one of: mean, median, min, p95, p99",
+                s
+            )),
+        }
+    }
+}
+
+impl Statistic {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Statistic::Mean => "mean",
+            Statistic::Median => "median",
+            Statistic::Min => "min",
+            Statistic::P95 => "p95",
+            Statistic::P99 => "p99",
+        }
+    }
+
+    // Percentiles missing from older results are computed from the times.
+    // With no times at all, the slowest run is the best guess at the tail.
+    pub fn of(&self, m: &Measurement) -> f64 {
+        let percentile = |stored: Option<f64>, p| {
+            stored
+                .or_else(|| stats::percentile(&m.times, p))
+                .unwrap_or(m.max)
+        };
+        match self {
+            Statistic::Mean => m.mean,
+            Statistic::Median => m.median,
+            Statistic::Min => m.min,
+            Statistic::P95 => percentile(m.p95, 0.95),
+            Statistic::P99 => percentile(m.p99, 0.99),
+        }
+    }
+}
+
+// How a regression is decided. `Ratio` compares the chosen statistic
+// against the threshold. `Welch` runs Welch's t-test on the raw times and
+// flags a regression when dev is significantly slower at the given alpha.
+// `Bootstrap` resamples the raw times to estimate a confidence interval for
+// the difference in means, and flags a regression when all of it is above
+// zero. `MannWhitney` runs the Mann-Whitney U test on the raw times, which
+// doesn't assume they're normally distributed, and flags a regression when
+// dev's times tend to be slower at the given alpha.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComparisonTest {
+    #[default]
+    Ratio,
+    Welch,
+    Bootstrap,
+    #[serde(rename = "mann-whitney")]
+    MannWhitney,
+}
+
+impl ComparisonTest {
+    // Whether the test works from each measurement's raw times rather than
+    // the summary statistics hyperfine reports alongside them.
+    pub fn needs_times(&self) -> bool {
+        match self {
+            ComparisonTest::Ratio => false,
+            ComparisonTest::Welch | ComparisonTest::Bootstrap | ComparisonTest::MannWhitney => true,
+        }
+    }
+}
+
+impl FromStr for ComparisonTest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ratio" => Ok(ComparisonTest::Ratio),
+            "welch" => Ok(ComparisonTest::Welch),
+            "bootstrap" => Ok(ComparisonTest::Bootstrap),
+            "mann-whitney" => Ok(ComparisonTest::MannWhitney),
+            _ => Err(format!(
+                "unknown test '{}'. expected one of: ratio, welch, bootstrap, mann-whitney",
+                s
+            )),
+        }
+    }
+}
+
+// What dev is compared against. `Branch` is the results of the baseline
+// branch in the results directory. `Rolling` is built from the most recent
+// runs in the history file, for checking a change against recent runs when
+// there's no released baseline to measure. `Ewma` is built from the same
+// runs, weighted toward the most recent ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BaselineMode {
+    #[default]
+    Branch,
+    Rolling,
+    Ewma,
+}
+
+impl FromStr for BaselineMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "branch" => Ok(BaselineMode::Branch),
+            "rolling" => Ok(BaselineMode::Rolling),
+            "ewma" => Ok(BaselineMode::Ewma),
+            _ => Err(format!(
+                "unknown baseline mode '{}'. expected one of: branch, rolling, ewma",
+                s
+            )),
+        }
+    }
+}
+
+// How the significance level is adjusted for the number of pairs compared
+// with a t-test. Comparing hundreds of pairs at a fixed alpha flags some of
+// them by chance alone. `Bonferroni` divides alpha by the number of pairs.
+// `Bh` is Benjamini-Hochberg, which controls the false discovery rate and
+// gives up less power than Bonferroni.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Correction {
+    #[default]
+    None,
+    Bonferroni,
+    Bh,
+}
+
+impl FromStr for Correction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Correction::None),
+            "bonferroni" => Ok(Correction::Bonferroni),
+            "bh" => Ok(Correction::Bh),
+            _ => Err(format!(
+                "unknown correction '{}'. expected one of: none, bonferroni, bh",
+                s
+            )),
+        }
+    }
+}
+
+// Whether a p-value is significant at `alpha`. A p-value at the level
+// counts, the same as each of the Benjamini-Hochberg steps.
+fn significant(p_value: f64, alpha: f64) -> bool {
+    p_value <= alpha
+}
+
+impl Correction {
+    // The significance level each of the p-values is held to.
+    fn alpha(&self, alpha: f64, p_values: &[f64]) -> f64 {
+        let m = p_values.len() as f64;
+        match self {
+            _ if p_values.is_empty() => alpha,
+            Correction::None => alpha,
+            Correction::Bonferroni => alpha / m,
+            // the largest k with the k-th smallest p-value under k/m * alpha.
+            // if there is none, nothing is significant at the smallest step.
+            Correction::Bh => {
+                let k = p_values
+                    .iter()
+                    .sorted_by(|a, b| a.total_cmp(b))
+                    .enumerate()
+                    .filter(|(i, p)| significant(**p, (*i + 1) as f64 / m * alpha))
+                    .map(|(i, _)| i + 1)
+                    .max()
+                    .unwrap_or(1);
+                k as f64 / m * alpha
+            }
+        }
+    }
+}
+
+// Whether a metric gets better by going down, like a duration, or by going
+// up, like a throughput. Configured per metric name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    #[default]
+    LowerIsBetter,
+    HigherIsBetter,
+}
+
+// Everything that controls how measurements are compared.
 #[derive(Debug, Clone, PartialEq)]
-pub struct MeasurementGroup {
-    pub version: String,
-    pub run: String,
-    pub measurement: Measurement,
+pub struct CalculateOptions {
+    pub thresholds: ThresholdConfig,
+    pub statistic: Statistic,
+    pub test: ComparisonTest,
+    pub alpha: f64,
+    // adjusts alpha for the number of pairs compared with a t-test
+    pub correction: Correction,
+    // compare against results from this branch rather than `baseline`
+    pub baseline_branch: Option<String>,
+    pub filter: Filter,
+    // stddev assumed for a baseline that has none, as a fraction of its mean
+    pub min_stddev: f64,
+    // smallest absolute change, in seconds, that can count as a regression or
+    // improvement. Fast commands can move by a large ratio without anyone
+    // noticing.
+    pub min_abs_delta: f64,
+    // confidence level of the interval reported around the baseline mean, and
+    // of the bootstrap's interval
+    pub confidence: f64,
+    // resamples drawn by the bootstrap test
+    pub bootstrap_iterations: usize,
+    // fraction of the times to drop from each end before comparing
+    pub trim: f64,
+    // coefficient of variation above which a measurement is too noisy to gate on
+    pub max_cv: Option<f64>,
+    // fraction of cpu time spent in the kernel above which a measurement was
+    // probably contaminated by something else running on the machine
+    pub max_system_ratio: Option<f64>,
+    // recorded on every calculation so archived results say what they measured
+    pub commit: Option<String>,
+    // recorded on every calculation instead of the time it was calculated, so
+    // identical results always give identical calculations
+    pub now: Option<DateTime<Utc>>,
+    // also read results files from subdirectories of the results directory
+    pub recursive: bool,
+    // for `compare`, only read the results measured on these branches from
+    // the reference and candidate directories
+    pub reference_branch: Option<String>,
+    pub candidate_branch: Option<String>,
+    // metrics that aren't lower-is-better, by metric name
+    pub directions: HashMap<String, Direction>,
+}
+
+impl Default for CalculateOptions {
+    fn default() -> Self {
+        CalculateOptions {
+            thresholds: ThresholdConfig::default(),
+            statistic: Statistic::default(),
+            test: ComparisonTest::default(),
+            alpha: 0.05,
+            correction: Correction::default(),
+            baseline_branch: None,
+            filter: Filter::default(),
+            min_stddev: 0.02,
+            min_abs_delta: 0.0,
+            confidence: 0.95,
+            bootstrap_iterations: 10_000,
+            trim: 0.0,
+            max_cv: None,
+            max_system_ratio: None,
+            commit: None,
+            now: None,
+            recursive: false,
+            reference_branch: None,
+            candidate_branch: None,
+            directions: HashMap::new(),
+        }
+    }
+}
+
+impl CalculateOptions {
+    pub fn baseline_branch(&self) -> &str {
+        self.baseline_branch.as_deref().unwrap_or("baseline")
+    }
+
+    pub fn direction(&self, metric: &str) -> Direction {
+        self.directions.get(metric).copied().unwrap_or_default()
+    }
+
+    pub fn validate(&self) -> Result<(), CalculateError> {
+        self.thresholds.validate()?;
+        if self.min_stddev.is_nan() || self.min_stddev <= 0.0 {
+            return Err(CalculateError::BadThresholdErr(
+                "min_stddev".to_owned(),
+                self.min_stddev,
+            ));
+        }
+        if !(self.min_abs_delta >= 0.0 && self.min_abs_delta.is_finite()) {
+            return Err(CalculateError::BadThresholdErr(
+                "min_abs_delta".to_owned(),
+                self.min_abs_delta,
+            ));
+        }
+        if let Some(cv) = self.max_cv.filter(|cv| cv.is_nan() || *cv <= 0.0) {
+            return Err(CalculateError::BadThresholdErr("max_cv".to_owned(), cv));
+        }
+        if let Some(ratio) = self.max_system_ratio.filter(|r| !(*r > 0.0 && *r <= 1.0)) {
+            return Err(CalculateError::BadThresholdErr(
+                "max_system_ratio".to_owned(),
+                ratio,
+            ));
+        }
+        if !(self.trim >= 0.0 && self.trim < 0.5) {
+            return Err(CalculateError::BadTrimErr(self.trim));
+        }
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            return Err(CalculateError::BadConfidenceErr(self.confidence));
+        }
+        if self.bootstrap_iterations == 0 {
+            return Err(CalculateError::BadBootstrapIterationsErr(
+                self.bootstrap_iterations,
+            ));
+        }
+        if self.alpha > 0.0 && self.alpha < 1.0 {
+            Ok(())
+        } else {
+            Err(CalculateError::BadAlphaErr(self.alpha))
+        }
+    }
+}
+
+// Identifies a project-metric pair. Using named fields rather than
+// a tuple makes it impossible to mix up the project and the metric.
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct MetricKey {
+    pub project: String,
+    pub metric: String,
+}
+
+impl MetricKey {
+    pub fn new(project: &str, metric: &str) -> Self {
+        MetricKey {
+            project: project.to_owned(),
+            metric: metric.to_owned(),
+        }
+    }
+
+    // The run portion of a filename has the form `{metric}_{project}.json`,
+    // or `.json.gz` for archived results.
+    // Metric names don't contain underscores but project names can.
+    pub fn from_run(run: &str) -> Self {
+        let mut parts = run.splitn(2, '_');
+        let metric = parts.next().unwrap_or("");
+        let project = parts
+            .next()
+            .unwrap_or("")
+            .trim_end_matches(".gz")
+            .trim_end_matches(".json");
+        MetricKey::new(project, metric)
+    }
+
+    // Calculations are named `{statistic}_{metric}_{project}.json`.
+    pub fn from_calculation(name: &str) -> Self {
+        MetricKey::from_run(name.split_once('_').map_or("", |(_, run)| run))
+    }
+}
+
+// How one project-metric pair moved between the results of two branches.
+// Pairs measured on only one of the branches have no value on the other.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricDiff {
+    pub project: String,
+    pub metric: String,
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+    pub pct_change: Option<f64>,
+    // moved by more than one stddev of the `from` measurement
+    pub moved: bool,
+}
+
+// Percent change from baseline to dev, so reports can say "12% slower".
+// There is no meaningful percent change from a zero baseline.
+fn pct_change(dev: f64, baseline: f64) -> Option<f64> {
+    stats::safe_ratio(dev - baseline, baseline).map(|r| r * 100.0)
+}
+
+// Whether dev is more than `threshold` times worse than baseline. With a
+// zero baseline there's no ratio to judge by, but any increase at all over
+// nothing is still infinitely worse.
+fn is_regression(difference: Option<f64>, dev: f64, baseline: f64, threshold: f64) -> bool {
+    difference.map_or(dev > baseline, |d| d > threshold)
+}
+
+// An improvement is the reciprocal of a regression: a 1.05 threshold flags
+// dev as an improvement when baseline takes more than 1.05 times as long.
+// Unlike subtracting from 2, this holds for thresholds of 2 and above.
+fn is_improvement(difference: f64, threshold: f64) -> bool {
+    difference < 1.0 / threshold
+}
+
+// Relative noise of a measurement. A zero mean with any spread at all
+// is infinitely noisy.
+fn coefficient_of_variation(m: &Measurement) -> f64 {
+    if m.stddev == 0.0 {
+        0.0
+    } else {
+        stats::safe_ratio(m.stddev, m.mean).unwrap_or(f64::INFINITY)
+    }
+}
+
+// The fraction of a measurement's cpu time that was spent in the kernel. A
+// command that used no cpu time at all spent none of it there.
+fn system_ratio(m: &Measurement) -> f64 {
+    stats::safe_ratio(m.system, m.user + m.system).unwrap_or(0.0)
 }
 
 // Given two measurements, return all the calculations. Calculations are
-// flagged as regressions or not regressions.
-fn calculate(metric: &str, dev: &Measurement, baseline: &Measurement) -> Vec<Calculation> {
+// flagged as regressions, improvements, or neither.
+fn calculate(
+    metric: &str,
+    dev: &Measurement,
+    baseline: &Measurement,
+    thresholds: &Thresholds,
+    opts: &CalculateOptions,
+) -> Vec<Calculation> {
     // choosing the current timestamp for all calculations to be the same.
     // this timestamp is not from the time of measurement becuase hyperfine
     // controls that. Since calculation is run directly after, this is fine.
-    let ts = Utc::now();
+    let ts = opts.now.unwrap_or_else(Utc::now);
+
+    let (dev, baseline) = (&dev.trimmed(opts.trim), &baseline.trimmed(opts.trim));
 
-    let median_threshold = 1.05; // 5% regression threshold
-    let median_difference = dev.median / baseline.median;
+    // the median threshold applies to whichever statistic was chosen
+    let stat_threshold = thresholds.median;
+    let statistic = opts.statistic;
+    let stat_dev = statistic.of(dev);
+    let stat_baseline = statistic.of(baseline);
+    let stat_difference = stats::safe_ratio(stat_dev, stat_baseline);
 
-    let stddev_threshold = 1.20; // 20% regression threshold
-    let stddev_difference = dev.stddev / baseline.stddev;
+    // the t-test and the bootstrap need at least two times on each side, and
+    // Mann-Whitney at least one. Without them there is no evidence of a
+    // regression either way.
+    let (mut diff_ci, mut u_statistic) = (None, None);
+    let (increased, decreased, p_value) = match opts.test {
+        ComparisonTest::Ratio => (
+            is_regression(stat_difference, stat_dev, stat_baseline, stat_threshold),
+            stat_difference.is_some_and(|d| is_improvement(d, stat_threshold)),
+            None,
+        ),
+        ComparisonTest::Bootstrap => {
+            diff_ci = stats::bootstrap_mean_difference(
+                &dev.times,
+                &baseline.times,
+                opts.bootstrap_iterations,
+                opts.confidence,
+            );
+            match diff_ci {
+                Some((low, high)) => (low > 0.0, high < 0.0, None),
+                None => (false, false, None),
+            }
+        }
+        ComparisonTest::Welch => match stats::welch_t_test(&dev.times, &baseline.times) {
+            Some(r) => (
+                significant(r.p_value, opts.alpha) && r.t > 0.0,
+                significant(r.p_value, opts.alpha) && r.t < 0.0,
+                Some(r.p_value),
+            ),
+            None => (false, false, None),
+        },
+        ComparisonTest::MannWhitney => {
+            match stats::mann_whitney_u_test(&dev.times, &baseline.times) {
+                Some(r) => {
+                    u_statistic = Some(r.u);
+                    // U is centered on half the pairs when neither is slower
+                    let center = (dev.times.len() * baseline.times.len()) as f64 / 2.0;
+                    (
+                        significant(r.p_value, opts.alpha) && r.u > center,
+                        significant(r.p_value, opts.alpha) && r.u < center,
+                        Some(r.p_value),
+                    )
+                }
+                None => (false, false, None),
+            }
+        }
+    };
+    // a higher-is-better metric regresses where the others would improve.
+    // stddev is noise whatever the metric, so it's always lower-is-better.
+    let direction = opts.direction(&MetricKey::from_run(metric).metric);
+    let (stat_regression, stat_improvement) = match direction {
+        Direction::LowerIsBetter => (increased, decreased),
+        Direction::HigherIsBetter => (decreased, increased),
+    };
+
+    // both the threshold and the absolute floor have to be passed
+    let beyond_floor = |dev: f64, baseline: f64| (dev - baseline).abs() >= opts.min_abs_delta;
+    let stat_beyond_floor = beyond_floor(stat_dev, stat_baseline);
+
+    // if either side is too noisy, or its command failed, neither of its
+    // calculations can be trusted
+    let noisy = opts.max_cv.is_some_and(|max| {
+        coefficient_of_variation(dev).abs() > max || coefficient_of_variation(baseline).abs() > max
+    });
+    let failed = dev.failed() || baseline.failed();
+    let suspect = opts
+        .max_system_ratio
+        .is_some_and(|max| system_ratio(dev) > max || system_ratio(baseline) > max);
+
+    let ci = stats::confidence_interval(
+        baseline.mean,
+        baseline.stddev,
+        baseline.times.len(),
+        opts.confidence,
+    );
+
+    let cv = stats::safe_ratio(dev.stddev, dev.mean);
+
+    let observed = Observed {
+        dev_min: dev.min,
+        dev_max: dev.max,
+        baseline_user: baseline.user,
+        baseline_system: baseline.system,
+    };
+
+    let stddev_threshold = thresholds.stddev;
+    let (stddev_rule, stddev_baseline) = if baseline.stddev == 0.0 {
+        (Rule::StddevFloor, opts.min_stddev * baseline.mean)
+    } else {
+        (Rule::Ratio, baseline.stddev)
+    };
+    let stddev_difference = stats::safe_ratio(dev.stddev, stddev_baseline);
+    let stddev_beyond_floor = beyond_floor(dev.stddev, stddev_baseline);
 
     vec![
         Calculation {
-            metric: ["median", metric].join("_"),
-            regression: median_difference > median_threshold,
-            ts: ts,
+            metric: [statistic.name(), metric].join("_"),
+            regression: stat_regression && stat_beyond_floor,
+            improvement: stat_improvement && stat_beyond_floor,
+            ts,
+            rule: Rule::Ratio,
+            direction,
+            noisy,
+            failed,
+            quarantined: false,
+            accepted: false,
+            suspect,
+            commit: opts.commit.clone(),
             data: Data {
-                threshold: median_threshold,
-                difference: median_difference,
-                baseline: baseline.median,
-                dev: dev.median,
+                threshold: stat_threshold,
+                difference: stat_difference,
+                pct_change: pct_change(stat_dev, stat_baseline),
+                p_value,
+                u_statistic,
+                alpha: p_value.map(|_| opts.alpha),
+                min_abs_delta: opts.min_abs_delta,
+                ci_low: ci.map(|(low, _)| low),
+                ci_high: ci.map(|(_, high)| high),
+                diff_ci_low: diff_ci.map(|(low, _)| low),
+                diff_ci_high: diff_ci.map(|(_, high)| high),
+                baseline: stat_baseline,
+                dev: stat_dev,
+                cv,
             },
+            observed: Some(observed.clone()),
         },
         Calculation {
             metric: ["stddev", metric].join("_"),
-            regression: stddev_difference > stddev_threshold,
-            ts: ts,
+            regression: is_regression(
+                stddev_difference,
+                dev.stddev,
+                stddev_baseline,
+                stddev_threshold,
+            ) && stddev_beyond_floor,
+            improvement: stddev_difference.is_some_and(|d| is_improvement(d, stddev_threshold))
+                && stddev_beyond_floor,
+            ts,
+            rule: stddev_rule,
+            direction: Direction::LowerIsBetter,
+            noisy,
+            failed,
+            quarantined: false,
+            accepted: false,
+            suspect,
+            commit: opts.commit.clone(),
             data: Data {
                 threshold: stddev_threshold,
                 difference: stddev_difference,
-                baseline: baseline.stddev,
+                pct_change: pct_change(dev.stddev, stddev_baseline),
+                p_value: None,
+                u_statistic: None,
+                alpha: None,
+                min_abs_delta: opts.min_abs_delta,
+                ci_low: None,
+                ci_high: None,
+                diff_ci_low: None,
+                diff_ci_high: None,
+                // the floored stddev, when it was judged by the floor
+                baseline: stddev_baseline,
                 dev: dev.stddev,
+                cv,
             },
+            observed: Some(observed),
         },
     ]
 }
 
-// Given a directory, read all files in the directory and return each
-// filename with the deserialized json contents of that file.
-fn measurements_from_files(
+// Results filenames have the form `{branch}_{run}`. Returns the branch and the run.
+fn split_filename(name: &str) -> (&str, &str) {
+    let mut parts = name.splitn(2, '_');
+    let branch = parts.next().unwrap_or("");
+    let run = parts.next().unwrap_or("");
+    (branch, run)
+}
+
+// The project-metric pair a results file was measured for, if it has a unicode filename.
+fn key_from_path(path: &Path) -> Option<MetricKey> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| MetricKey::from_run(split_filename(name).1))
+}
+
+// Reads a results file, transparently decompressing it if it's gzipped.
+pub fn read_results_file(path: &Path) -> Result<String, CalculateError> {
+    let read_err =
+        |e| CalculateError::from(IOError::BadFileContentsErr(path.to_path_buf(), Some(e)));
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut contents = String::new();
+        fs::File::open(path)
+            .and_then(|f| GzDecoder::new(f).read_to_string(&mut contents))
+            .map_err(read_err)?;
+        Ok(contents)
+    } else {
+        fs::read_to_string(path).map_err(read_err)
+    }
+}
+
+// Results files are json, or gzipped json. Anything else that ends up in the
+// results directory, like a README or a .DS_Store, is not ours to read.
+fn is_results_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    path.is_file() && (name.ends_with(".json") || name.ends_with(".json.gz"))
+}
+
+// Given a directory, return the paths of all the json results files in it.
+// With `recursive`, results files in subdirectories are included too.
+fn results_files(
     results_directory: &Path,
-) -> Result<Vec<(PathBuf, Measurements)>, CalculateError> {
-    fs::read_dir(results_directory)
-        .or_else(|e| Err(IOError::ReadErr(results_directory.to_path_buf(), Some(e))))
-        .or_else(|e| Err(CalculateError::CalculateIOError(e)))?
-        .into_iter()
+    recursive: bool,
+) -> Result<Vec<PathBuf>, CalculateError> {
+    let entries = fs::read_dir(results_directory)
+        .map_err(|e| IOError::ReadErr(results_directory.to_path_buf(), Some(e)))?
         .map(|entry| {
-            let ent: DirEntry = entry
-                .or_else(|e| Err(IOError::ReadErr(results_directory.to_path_buf(), Some(e))))
-                .or_else(|e| Err(CalculateError::CalculateIOError(e)))?;
+            let ent: DirEntry =
+                entry.map_err(|e| IOError::ReadErr(results_directory.to_path_buf(), Some(e)))?;
 
             Ok(ent.path())
         })
-        .collect::<Result<Vec<PathBuf>, CalculateError>>()?
+        .collect::<Result<Vec<PathBuf>, CalculateError>>()?;
+
+    let mut paths = vec![];
+    for path in entries {
+        if recursive && path.is_dir() {
+            paths.extend(results_files(&path, recursive)?);
+        } else if is_results_file(&path) {
+            paths.push(path);
+        } else {
+            tracing::debug!("skipping {}, it is not a results file", path.display());
+        }
+    }
+
+    Ok(paths)
+}
+
+// Returns the names of every project with results in the directory.
+pub fn project_names(
+    results_directory: &Path,
+    recursive: bool,
+) -> Result<Vec<String>, CalculateError> {
+    Ok(results_files(results_directory, recursive)?
+        .iter()
+        .filter_map(|path| key_from_path(path))
+        .map(|key| key.project)
+        .unique()
+        .collect())
+}
+
+// Given a directory, read all the results files in the directory that pass
+// the filter and return each filename with the deserialized json contents of
+// that file. Filtered files are never read.
+fn measurements_from_files(
+    results_directory: &Path,
+    filter: &Filter,
+    recursive: bool,
+) -> Result<Vec<(PathBuf, Measurements)>, CalculateError> {
+    results_files(results_directory, recursive)?
         .iter()
         .filter(|path| {
-            path.extension()
-                .and_then(|ext| ext.to_str())
-                .map_or(false, |ext| ext.ends_with("json"))
-        })
-        .map(|path| {
-            fs::read_to_string(path)
-                .or_else(|e| Err(IOError::BadFileContentsErr(path.clone(), Some(e))))
-                .or_else(|e| Err(CalculateError::CalculateIOError(e)))
-                .and_then(|contents| {
-                    serde_json::from_str::<Measurements>(&contents)
-                        .or_else(|e| Err(CalculateError::BadJSONErr(path.clone(), Some(e))))
-                })
-                .map(|m| (path.clone(), m))
+            let included =
+                key_from_path(path).is_none_or(|key| filter.includes(&key.project, &key.metric));
+            if !included {
+                tracing::debug!("skipping {}, it is excluded by the filter", path.display());
+            }
+            included
         })
+        .map(|path| read_measurements(path).map(|m| (path.clone(), m)))
         .collect()
 }
 
+// Reads and validates the measurements in one results file.
+fn read_measurements(path: &Path) -> Result<Measurements, CalculateError> {
+    tracing::debug!("reading {}", path.display());
+    parse_measurements(path, &read_results_file(path)?)
+}
+
+// Parses and validates the contents of a results file. `path` is only used
+// to say where the contents came from in errors.
+fn parse_measurements(path: &Path, contents: &str) -> Result<Measurements, CalculateError> {
+    let ms = serde_json::from_str::<Measurements>(contents)
+        .map_err(|e| CalculateError::BadJSONErr(path.to_path_buf(), Some(e)))?;
+    ms.results.iter().try_for_each(|m| m.validate(path))?;
+    Ok(ms)
+}
+
+// Checks every results file in the directory, so that all the problems
+// can be fixed at once rather than one failed calculate at a time.
+// Returns the number of files checked and a problem for each bad file.
+pub fn validate_results(
+    results_directory: &Path,
+    recursive: bool,
+) -> Result<(usize, Vec<CalculateError>), CalculateError> {
+    let paths = results_files(results_directory, recursive)?;
+    let problems = paths
+        .iter()
+        .filter_map(|path| {
+            read_measurements(path)
+                .and_then(|ms| single_measurement(path, &ms).map(|_| ()))
+                .err()
+        })
+        .collect();
+    Ok((paths.len(), problems))
+}
+
+// Results files are prefixed with the branch they were measured on.
+fn is_branch_file(path: &Path, branch: &str) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_prefix(branch))
+        .is_some_and(|rest| rest.starts_with('_'))
+}
+
+// The way we're running hyperfine, each file contains exactly one measurement.
+// Anything else means the file wasn't produced by the measure step.
+fn single_measurement<'a>(
+    path: &Path,
+    measurements: &'a Measurements,
+) -> Result<&'a Measurement, CalculateError> {
+    match &measurements.results[..] {
+        [m] => Ok(m),
+        ms => Err(CalculateError::BadMeasurementCountErr(
+            path.to_path_buf(),
+            ms.len(),
+        )),
+    }
+}
+
+// Given all the measurements for one project-metric pair, sorted by branch name,
+// pick out the baseline and dev measurements to compare.
+fn pair_group<'a, 'b>(
+    groups: &[&'b MeasurementGroup<'a>],
+    opts: &CalculateOptions,
+) -> Result<(&'b MeasurementGroup<'a>, &'b MeasurementGroup<'a>), CalculateError> {
+    let bad_group_size = || {
+        let gs: Vec<(String, String)> = groups
+            .iter()
+            .map(|g| (g.version.to_owned(), g.run.to_owned()))
+            .collect();
+        CalculateError::BadGroupSizeErr(groups.len(), gs)
+    };
+
+    match &opts.baseline_branch {
+        // an explicitly requested baseline branch is picked out of however
+        // many branches were measured.
+        Some(branch) => {
+            let baseline = groups.iter().find(|g| g.version == branch).ok_or_else(|| {
+                CalculateError::BaselineBranchNotFoundErr(
+                    branch.clone(),
+                    groups.first().map_or("", |g| g.run).to_owned(),
+                )
+            })?;
+            let dev = groups
+                .iter()
+                .find(|g| g.version == "dev")
+                .ok_or_else(bad_group_size)?;
+            Ok((baseline, dev))
+        }
+        None => match groups {
+            [baseline, dev] => {
+                if dev.version == "dev" && baseline.version == "baseline" {
+                    Ok((baseline, dev))
+                } else {
+                    Err(CalculateError::BadBranchNameErr(
+                        baseline.version.to_owned(),
+                        dev.version.to_owned(),
+                    ))
+                }
+            }
+            _ => Err(bad_group_size()),
+        },
+    }
+}
+
 // Given a list of filename-measurement pairs, detect any regressions by grouping
 // measurements together by filename.
 fn calculate_regressions(
     measurements: &[(&PathBuf, &Measurement)],
-) -> Result<Vec<Calculation>, CalculateError> {
+    opts: &CalculateOptions,
+) -> Result<Comparison, CalculateError> {
     /*
         Strategy of this function body:
         1. [Measurement] -> [MeasurementGroup]
         2. Sort the MeasurementGroups
-        3. Group the MeasurementGroups by "run"
-        4. Call `calculate` with the two resulting Measurements as input
+        3. Group the MeasurementGroups by their MetricKey
+        4. Call `calculate` with the two resulting Measurements as input,
+       or note the key as missing or new if either side has no Measurement
     */
+    let _span = tracing::info_span!("calculate").entered();
 
     let mut measurement_groups: Vec<MeasurementGroup> = measurements
         .iter()
@@ -158,80 +1228,466 @@ fn calculate_regressions(
                         .ok_or_else(|| IOError::FilenameNotUnicodeErr(p.to_path_buf()))
                 })
                 .map(|name| {
-                    let parts: Vec<&str> = name.split("_").collect();
+                    let (version, run) = split_filename(name);
                     MeasurementGroup {
-                        version: parts[0].to_owned(),
-                        run: parts[1..].join("_"),
-                        measurement: (*m).clone(),
+                        version,
+                        run,
+                        key: MetricKey::from_run(run),
+                        measurement: m,
                     }
                 })
         })
-        .collect::<Result<Vec<MeasurementGroup>, IOError>>()
-        .or_else(|e| Err(CalculateError::CalculateIOError(e)))?;
+        .collect::<Result<Vec<MeasurementGroup>, IOError>>()?;
 
-    measurement_groups.sort_by(|x, y| (&x.run, &x.version).cmp(&(&y.run, &y.version)));
+    measurement_groups.sort_by(|x, y| (&x.key, x.version).cmp(&(&y.key, y.version)));
 
     // locking up mutation
     let sorted_measurement_groups = measurement_groups;
 
-    let calculations: Vec<Calculation> = sorted_measurement_groups
-        .iter()
-        .group_by(|x| &x.run)
-        .into_iter()
-        .map(|(_, g)| {
-            let mut groups: Vec<&MeasurementGroup> = g.collect();
-            groups.sort_by(|x, y| x.version.cmp(&y.version));
-
-            match groups.len() {
-                2 => {
-                    let dev = &groups[1];
-                    let baseline = &groups[0];
-
-                    if dev.version == "dev" && baseline.version == "baseline" {
-                        Ok(calculate(&dev.run, &dev.measurement, &baseline.measurement))
-                    } else {
-                        Err(CalculateError::BadBranchNameErr(
-                            baseline.version.clone(),
-                            dev.version.clone(),
-                        ))
-                    }
-                }
-                i => {
-                    let gs: Vec<MeasurementGroup> = groups.into_iter().map(|x| x.clone()).collect();
-                    Err(CalculateError::BadGroupSizeErr(i, gs))
-                }
+    // two results for the same pair on one branch, like a file and its
+    // gzipped copy, leave no way to tell which one to compare
+    if let Some(dup) = sorted_measurement_groups
+        .windows(2)
+        .find(|w| (&w[0].key, w[0].version) == (&w[1].key, w[1].version))
+        .map(|w| &w[0])
+    {
+        return Err(CalculateError::DuplicateMetricErr(
+            dup.version.to_owned(),
+            dup.key.project.clone(),
+            dup.key.metric.clone(),
+        ));
+    }
+
+    let mut comparison = Comparison {
+        calculations: vec![],
+        missing: vec![],
+        new: vec![],
+    };
+    let mut pairs = vec![];
+    for (key, g) in &sorted_measurement_groups.iter().group_by(|x| &x.key) {
+        let mut groups: Vec<&MeasurementGroup> = g.collect();
+        groups.sort_by(|x, y| x.version.cmp(y.version));
+
+        // one side without the other has nothing to be compared to. dev
+        // measured alongside other branches but not the requested baseline
+        // is left to pair_group to report.
+        let has_baseline = groups.iter().any(|g| g.version == opts.baseline_branch());
+        let has_dev = groups.iter().any(|g| g.version == "dev");
+        if has_baseline && !has_dev {
+            tracing::debug!(project = %key.project, metric = %key.metric, "unmatched: no dev results");
+            comparison.missing.push(key.clone());
+            continue;
+        }
+        if has_dev && groups.len() == 1 {
+            tracing::debug!(project = %key.project, metric = %key.metric, "unmatched: no baseline results");
+            comparison.new.push(key.clone());
+            continue;
+        }
+
+        let (baseline, dev) = pair_group(&groups, opts)?;
+        // a results file with the summary statistics but no times would
+        // otherwise never regress under a distribution test
+        if opts.test.needs_times() {
+            if let Some(g) = [baseline, dev]
+                .iter()
+                .find(|g| g.measurement.times.is_empty())
+            {
+                return Err(CalculateError::MissingTimesErr(
+                    g.version.to_owned(),
+                    key.project.clone(),
+                    key.metric.clone(),
+                ));
             }
+        }
+        tracing::debug!(
+            project = %key.project,
+            metric = %key.metric,
+            "matched {} with {}",
+            baseline.version,
+            dev.version
+        );
+        pairs.push((baseline, dev));
+    }
+
+    // every pair compared with a t-test counts toward the correction
+    let p_values: Vec<f64> = match opts.test {
+        ComparisonTest::Ratio | ComparisonTest::Bootstrap => vec![],
+        ComparisonTest::Welch => pairs
+            .iter()
+            .filter_map(|(baseline, dev)| {
+                stats::welch_t_test(
+                    &stats::trim(&dev.measurement.times, opts.trim),
+                    &stats::trim(&baseline.measurement.times, opts.trim),
+                )
+            })
+            .map(|r| r.p_value)
+            .collect(),
+        ComparisonTest::MannWhitney => pairs
+            .iter()
+            .filter_map(|(baseline, dev)| {
+                stats::mann_whitney_u_test(
+                    &stats::trim(&dev.measurement.times, opts.trim),
+                    &stats::trim(&baseline.measurement.times, opts.trim),
+                )
+            })
+            .map(|r| r.p_value)
+            .collect(),
+    };
+    let opts = &CalculateOptions {
+        alpha: opts.correction.alpha(opts.alpha, &p_values),
+        ..opts.clone()
+    };
+
+    comparison.calculations = pairs
+        .iter()
+        .flat_map(|(baseline, dev)| {
+            calculate(
+                dev.run,
+                dev.measurement,
+                baseline.measurement,
+                &opts.thresholds.resolve(&dev.key),
+                opts,
+            )
         })
-        .collect::<Result<Vec<Vec<Calculation>>, CalculateError>>()?
-        .concat();
+        .collect();
+    tracing::info!(
+        "compared {} pairs, {} regressed",
+        pairs.len(),
+        comparison
+            .calculations
+            .iter()
+            .filter(|c| c.regression)
+            .count()
+    );
 
-    Ok(calculations)
+    Ok(comparison)
 }
 
-// Top-level function. Given a path for the result directory, call the above
-// functions to compare and collect calculations. Calculations include both
-// metrics that fall within the threshold and regressions.
-pub fn regressions(results_directory: &PathBuf) -> Result<Vec<Calculation>, CalculateError> {
-    measurements_from_files(Path::new(&results_directory)).and_then(|v| {
-        // exit early with an Err if there are no results to process
-        if v.len() <= 0 {
-            Err(CalculateError::NoResultsErr(results_directory.clone()))
-        // we expect two runs for each project-metric pairing: one for each branch, baseline
-        // and dev. An odd result count is unexpected.
-        } else if v.len() % 2 == 1 {
-            Err(CalculateError::OddResultsCountErr(
-                v.len(),
-                results_directory.clone(),
+// Compares the results of two branches in the same results directory
+// without any thresholds, so we can see what moved between them. Pairs
+// are matched by MetricKey and sorted by it.
+pub fn diff(
+    results_directory: &Path,
+    from_branch: &str,
+    to_branch: &str,
+    statistic: Statistic,
+    filter: &Filter,
+) -> Result<Vec<MetricDiff>, CalculateError> {
+    let files = measurements_from_files(results_directory, filter, false)?;
+    if files.is_empty() {
+        return Err(CalculateError::NoResultsErr(
+            results_directory.to_path_buf(),
+        ));
+    }
+
+    // every pair measured on a branch, keyed so the two sides can be matched up
+    let branch = |name: &str| -> Result<HashMap<MetricKey, &Measurement>, CalculateError> {
+        let measurements = files
+            .iter()
+            .filter(|(p, _)| is_branch_file(p, name))
+            .filter_map(|(p, ms)| {
+                key_from_path(p).map(|key| single_measurement(p, ms).map(|m| (key, m)))
+            })
+            .collect::<Result<HashMap<MetricKey, &Measurement>, CalculateError>>()?;
+
+        if measurements.is_empty() {
+            Err(CalculateError::NoBranchResultsErr(
+                name.to_owned(),
+                results_directory.to_path_buf(),
             ))
         } else {
-            // otherwise, we can do our comparisons
-            let measurements = v
-                .iter()
-                // the way we're running these, the files will each contain exactly one measurement, hence `results[0]`
-                .map(|(p, ms)| (p, &ms.results[0]))
-                .collect::<Vec<(&PathBuf, &Measurement)>>();
-
-            calculate_regressions(&measurements[..])
+            Ok(measurements)
+        }
+    };
+    let (from, to) = (branch(from_branch)?, branch(to_branch)?);
+
+    Ok(from
+        .keys()
+        .chain(to.keys())
+        .unique()
+        .sorted()
+        .map(|key| {
+            let (f, t) = (from.get(key), to.get(key));
+            let (from_stat, to_stat) = (f.map(|m| statistic.of(m)), t.map(|m| statistic.of(m)));
+            let (pct, moved) = match (f, from_stat, to_stat) {
+                (Some(f), Some(a), Some(b)) => (pct_change(b, a), (b - a).abs() > f.stddev),
+                _ => (None, false),
+            };
+            MetricDiff {
+                project: key.project.clone(),
+                metric: key.metric.clone(),
+                from: from_stat,
+                to: to_stat,
+                pct_change: pct,
+                moved,
+            }
+        })
+        .collect())
+}
+
+// Every results file in `dir` measured on `only`, renamed as if it was
+// measured on `branch`. Without `only`, the directory has to hold results
+// from a single branch, since the same pair from two branches would collide
+// once renamed.
+fn renamed_results(
+    dir: &Path,
+    only: Option<&str>,
+    branch: &str,
+    opts: &CalculateOptions,
+) -> Result<Vec<(PathBuf, Measurement)>, CalculateError> {
+    let files = measurements_from_files(dir, &opts.filter, opts.recursive)?;
+    if files.is_empty() {
+        return Err(CalculateError::NoResultsErr(dir.to_path_buf()));
+    }
+    let files: Vec<&(PathBuf, Measurements)> = match only {
+        Some(only) => {
+            let files: Vec<_> = files
+                .iter()
+                .filter(|(p, _)| is_branch_file(p, only))
+                .collect();
+            if files.is_empty() {
+                return Err(CalculateError::NoBranchResultsErr(
+                    only.to_owned(),
+                    dir.to_path_buf(),
+                ));
+            }
+            files
+        }
+        None => {
+            let branches: Vec<String> = files
+                .iter()
+                .filter_map(|(p, _)| p.file_name()?.to_str())
+                .map(|name| split_filename(name).0.to_owned())
+                .sorted()
+                .dedup()
+                .collect();
+            if branches.len() > 1 {
+                return Err(CalculateError::MixedBranchesErr(
+                    dir.to_path_buf(),
+                    branches,
+                ));
+            }
+            files.iter().collect()
+        }
+    };
+    files
+        .into_iter()
+        .map(|(p, ms)| {
+            let run = p
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| split_filename(name).1)
+                .ok_or_else(|| IOError::FilenameNotUnicodeErr(p.clone()))?;
+            let m = single_measurement(p, ms)?;
+            Ok((PathBuf::from([branch, "_", run].join("")), m.clone()))
+        })
+        .collect()
+}
+
+// Compares the results in two directories directly, treating `reference`
+// as the baseline whatever branches either side was measured on. Pairs are
+// matched by MetricKey, so quick A/B experiments don't have to be named
+// baseline and dev.
+pub fn compare(
+    reference: &Path,
+    candidate: &Path,
+    opts: &CalculateOptions,
+) -> Result<Comparison, CalculateError> {
+    opts.validate()?;
+
+    let renamed = [
+        renamed_results(
+            reference,
+            opts.reference_branch.as_deref(),
+            "baseline",
+            opts,
+        )?,
+        renamed_results(candidate, opts.candidate_branch.as_deref(), "dev", opts)?,
+    ]
+    .concat();
+    let measurements: Vec<(&PathBuf, &Measurement)> = renamed.iter().map(|(p, m)| (p, m)).collect();
+
+    calculate_regressions(
+        &measurements,
+        &CalculateOptions {
+            baseline_branch: None,
+            ..opts.clone()
+        },
+    )
+}
+
+// Compares a results file that wasn't read from a directory, like hyperfine
+// output piped to stdin, against the reference directory's results for the
+// one project-metric pair the filter names. `source` says where the
+// contents came from in errors.
+pub fn compare_samples(
+    reference: &Path,
+    source: &Path,
+    contents: &str,
+    opts: &CalculateOptions,
+) -> Result<Comparison, CalculateError> {
+    opts.validate()?;
+
+    // without a filename, the filter is all there is to name the samples by
+    let key = match (&opts.filter.projects[..], &opts.filter.metrics[..]) {
+        ([project], [metric]) => MetricKey::new(project, metric),
+        (projects, metrics) => {
+            return Err(CalculateError::SamplesPairErr(
+                projects.len(),
+                metrics.len(),
+            ))
+        }
+    };
+    if contents.trim().is_empty() {
+        return Err(CalculateError::EmptySamplesErr(source.to_path_buf()));
+    }
+    let ms = parse_measurements(source, contents)?;
+    let dev = single_measurement(source, &ms)?.clone();
+    let dev_path = PathBuf::from(format!("dev_{}_{}.json", key.metric, key.project));
+
+    let renamed: Vec<(PathBuf, Measurement)> = renamed_results(
+        reference,
+        opts.reference_branch.as_deref(),
+        "baseline",
+        opts,
+    )?
+    .into_iter()
+    .chain(std::iter::once((dev_path, dev)))
+    .collect();
+    let measurements: Vec<(&PathBuf, &Measurement)> = renamed.iter().map(|(p, m)| (p, m)).collect();
+
+    calculate_regressions(
+        &measurements,
+        &CalculateOptions {
+            baseline_branch: None,
+            ..opts.clone()
+        },
+    )
+}
+
+// Compares the dev results in a directory against baselines that weren't
+// read from it, like the rolling baselines built from the history. Each
+// baseline is keyed by the run portion of a results filename. Anything in
+// the directory that wasn't measured on dev is ignored.
+pub fn regressions_against(
+    results_directory: &Path,
+    baselines: &[(String, Measurement)],
+    opts: &CalculateOptions,
+) -> Result<Comparison, CalculateError> {
+    opts.validate()?;
+
+    let files = measurements_from_files(results_directory, &opts.filter, opts.recursive)?;
+    let dev = files
+        .iter()
+        .filter(|(p, _)| is_branch_file(p, "dev"))
+        .map(|(p, ms)| single_measurement(p, ms).map(|m| (p.clone(), m.clone())))
+        .collect::<Result<Vec<(PathBuf, Measurement)>, CalculateError>>()?;
+    if dev.is_empty() {
+        return Err(CalculateError::NoBranchResultsErr(
+            "dev".to_owned(),
+            results_directory.to_path_buf(),
+        ));
+    }
+    let baselines = baselines
+        .iter()
+        .filter(|(run, _)| {
+            let key = MetricKey::from_run(run);
+            opts.filter.includes(&key.project, &key.metric)
+        })
+        .map(|(run, m)| (PathBuf::from(["baseline_", run].join("")), m.clone()));
+
+    let renamed: Vec<(PathBuf, Measurement)> = dev.into_iter().chain(baselines).collect();
+    let measurements: Vec<(&PathBuf, &Measurement)> = renamed.iter().map(|(p, m)| (p, m)).collect();
+
+    calculate_regressions(
+        &measurements,
+        &CalculateOptions {
+            baseline_branch: None,
+            ..opts.clone()
+        },
+    )
+}
+
+// Compares a single project-metric pair as soon as it's measured, instead
+// of waiting for the whole run, so a run can stop at its first regression.
+// `run` is the run portion of a results filename, and `branch`'s results for
+// it are compared against the baseline's in the same directory. A pair with
+// no baseline results has nothing to be compared to. Each pair is compared
+// on its own, so no multiple comparison correction is applied.
+pub fn pair_regressions(
+    results_directory: &Path,
+    branch: &str,
+    run: &str,
+    opts: &CalculateOptions,
+) -> Result<Vec<Calculation>, CalculateError> {
+    opts.validate()?;
+
+    let read = |branch: &str| -> Result<Option<Measurement>, CalculateError> {
+        let path = results_directory.join([branch, "_", run].join(""));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let ms = read_measurements(&path)?;
+        single_measurement(&path, &ms).map(|m| Some(m.clone()))
+    };
+    let (dev, baseline) = match (read(branch)?, read(opts.baseline_branch())?) {
+        (Some(dev), Some(baseline)) => (dev, baseline),
+        _ => return Ok(vec![]),
+    };
+    let dev_path = PathBuf::from(["dev_", run].join(""));
+    let baseline_path = PathBuf::from(["baseline_", run].join(""));
+
+    calculate_regressions(
+        &[(&dev_path, &dev), (&baseline_path, &baseline)],
+        &CalculateOptions {
+            baseline_branch: None,
+            ..opts.clone()
+        },
+    )
+    .map(|c| c.calculations)
+}
+
+// Top-level function. Given a path for the result directory, call the above
+// functions to compare and collect calculations. Calculations include both
+// metrics that fall within the threshold and regressions.
+pub fn regressions(
+    results_directory: &PathBuf,
+    opts: &CalculateOptions,
+) -> Result<Comparison, CalculateError> {
+    opts.validate()?;
+
+    let files = {
+        let _span =
+            tracing::info_span!("load_results", dir = %results_directory.display()).entered();
+        measurements_from_files(Path::new(&results_directory), &opts.filter, opts.recursive)
+            .inspect(|v| tracing::info!("read {} results files", v.len()))
+    };
+
+    files.and_then(|v| {
+        // exit early with an Err if there are no results to process
+        if v.is_empty() && opts.filter != Filter::default() {
+            Err(CalculateError::NoFilteredResultsErr(
+                results_directory.clone(),
+            ))
+        } else if v.is_empty() {
+            Err(CalculateError::NoResultsErr(results_directory.clone()))
+        // pointing at a directory of only dev results is an easy mistake to make,
+        // and would otherwise surface as a confusing group size error.
+        } else if !v
+            .iter()
+            .any(|(p, _)| is_branch_file(p, opts.baseline_branch()))
+        {
+            Err(CalculateError::NoBaselineResultsErr(
+                results_directory.clone(),
+            ))
+        } else {
+            // otherwise, we can do our comparisons
+            let measurements = v
+                .iter()
+                .map(|(p, ms)| single_measurement(p, ms).map(|m| (p, m)))
+                .collect::<Result<Vec<(&PathBuf, &Measurement)>, CalculateError>>()?;
+
+            calculate_regressions(&measurements[..], opts)
         }
     })
 }
@@ -239,34 +1695,55 @@ pub fn regressions(results_directory: &PathBuf) -> Result<Vec<Calculation>, Calc
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::measure::TmpDir;
 
-    #[test]
-    fn detects_5_percent_regression() {
-        let dev = Measurement {
+    // A measurement with none of the detail a test doesn't need: every
+    // statistic is `mean`, and so is each of its cpu times.
+    fn measurement(mean: f64, stddev: f64) -> Measurement {
+        Measurement {
             command: "some command".to_owned(),
-            mean: 1.06,
-            stddev: 1.06,
-            median: 1.06,
-            user: 1.06,
-            system: 1.06,
-            min: 1.06,
-            max: 1.06,
+            mean,
+            stddev,
+            median: mean,
+            user: mean,
+            system: mean,
+            min: mean,
+            max: mean,
             times: vec![],
-        };
+            p95: None,
+            p99: None,
+            exit_codes: vec![],
+        }
+    }
 
-        let baseline = Measurement {
-            command: "some command".to_owned(),
-            mean: 1.00,
-            stddev: 1.00,
-            median: 1.00,
-            user: 1.00,
-            system: 1.00,
-            min: 1.00,
-            max: 1.00,
-            times: vec![],
-        };
+    // A measurement of `times`, summarized the way hyperfine would.
+    fn sampled(times: Vec<f64>) -> Measurement {
+        let (mean, stddev) = (
+            stats::mean(&times).unwrap(),
+            stats::variance(&times).unwrap().sqrt(),
+        );
+        Measurement {
+            median: stats::median(&times).unwrap(),
+            min: times.iter().copied().fold(f64::INFINITY, f64::min),
+            max: times.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            times,
+            ..measurement(mean, stddev)
+        }
+    }
+
+    #[test]
+    fn detects_5_percent_regression() {
+        let dev = measurement(1.06, 1.06);
+
+        let baseline = measurement(1.00, 1.00);
 
-        let calculations = calculate("test_metric", &dev, &baseline);
+        let calculations = calculate(
+            "test_metric",
+            &dev,
+            &baseline,
+            &Thresholds::default(),
+            &CalculateOptions::default(),
+        );
         let regressions: Vec<&Calculation> =
             calculations.iter().filter(|calc| calc.regression).collect();
 
@@ -274,5 +1751,1410 @@ mod tests {
         println!("{:#?}", regressions);
         assert_eq!(regressions.len(), 1);
         assert_eq!(regressions[0].metric, "median_test_metric");
+        assert_eq!(regressions[0].commit, None);
+
+        let opts = CalculateOptions {
+            commit: Some("abc123".to_owned()),
+            ..CalculateOptions::default()
+        };
+        let calculations = calculate(
+            "test_metric",
+            &dev,
+            &baseline,
+            &Thresholds::default(),
+            &opts,
+        );
+        assert!(calculations
+            .iter()
+            .all(|c| c.commit.as_deref() == Some("abc123")));
+    }
+
+    #[test]
+    fn records_a_fixed_time_when_given() {
+        let measurement = |median: f64| Measurement {
+            times: vec![median],
+            ..measurement(median, 0.1)
+        };
+        let now = Utc.ymd(1970, 1, 1).and_hms(0, 0, 0);
+        let opts = CalculateOptions {
+            now: Some(now),
+            ..CalculateOptions::default()
+        };
+        let calcs = || {
+            calculate(
+                "test_metric",
+                &measurement(1.5),
+                &measurement(1.0),
+                &Thresholds::default(),
+                &opts,
+            )
+        };
+
+        assert!(calcs().iter().all(|c| c.ts == now));
+        // so the serialized calculations are byte for byte the same
+        assert_eq!(
+            serde_json::to_string(&calcs()).unwrap(),
+            serde_json::to_string(&calcs()).unwrap()
+        );
+    }
+
+    #[test]
+    fn detects_variance_regression_with_stable_median() {
+        let measurement = |stddev: f64| measurement(1.00, stddev);
+
+        let calculations = calculate(
+            "test_metric",
+            &measurement(0.20),
+            &measurement(0.10),
+            &Thresholds::default(),
+            &CalculateOptions::default(),
+        );
+        let regressed: Vec<(&str, bool)> = calculations
+            .iter()
+            .map(|c| (&c.metric[..], c.regression))
+            .collect();
+
+        assert_eq!(
+            regressed,
+            vec![("median_test_metric", false), ("stddev_test_metric", true)]
+        );
+    }
+
+    #[test]
+    fn mean_and_median_can_disagree() {
+        // a single slow outlier drags the mean up without moving the median or min
+        let dev = Measurement {
+            median: 1.00,
+            min: 1.00,
+            max: 4.00,
+            ..measurement(1.30, 1.00)
+        };
+
+        let baseline = measurement(1.00, 1.00);
+
+        let regressed = |statistic| {
+            calculate(
+                "test_metric",
+                &dev,
+                &baseline,
+                &Thresholds::default(),
+                &CalculateOptions {
+                    statistic,
+                    ..CalculateOptions::default()
+                },
+            )
+            .iter()
+            .filter(|calc| calc.regression)
+            .map(|calc| calc.metric.clone())
+            .collect::<Vec<String>>()
+        };
+
+        assert_eq!(regressed(Statistic::Mean), vec!["mean_test_metric"]);
+        assert!(regressed(Statistic::Median).is_empty());
+        assert!(regressed(Statistic::Min).is_empty());
+    }
+
+    #[test]
+    fn records_what_each_side_observed() {
+        let measurement = |times: Vec<f64>, user, system| Measurement {
+            user,
+            system,
+            ..sampled(times)
+        };
+        let baseline = measurement(vec![1.0, 1.1, 0.9], 0.8, 0.1);
+        let dev = measurement(vec![1.2, 1.3, 1.1, 1.25, 9.0], 0.9, 0.2);
+
+        let observed = |trim| {
+            calculate(
+                "test_metric",
+                &dev,
+                &baseline,
+                &Thresholds::default(),
+                &CalculateOptions {
+                    trim,
+                    ..CalculateOptions::default()
+                },
+            )
+            .remove(0)
+            .observed
+            .unwrap()
+        };
+        assert_eq!(
+            observed(0.0),
+            Observed {
+                dev_min: 1.1,
+                dev_max: 9.0,
+                baseline_user: 0.8,
+                baseline_system: 0.1,
+            }
+        );
+        // the range is of the times that were compared
+        assert_eq!(observed(0.2).dev_max, 1.3);
+
+        // calculations from before it was recorded still load
+        let mut json = serde_json::to_value(
+            calculate(
+                "test_metric",
+                &dev,
+                &baseline,
+                &Thresholds::default(),
+                &CalculateOptions::default(),
+            )
+            .remove(0),
+        )
+        .unwrap();
+        json.as_object_mut().unwrap().remove("observed");
+        let old: Calculation = serde_json::from_value(json).unwrap();
+        assert_eq!(old.observed, None);
+    }
+
+    #[test]
+    fn reports_dev_cv() {
+        let cv = |dev: &Measurement| {
+            calculate(
+                "test_metric",
+                dev,
+                &measurement(1.0, 0.1),
+                &Thresholds::default(),
+                &CalculateOptions::default(),
+            )
+            .remove(0)
+            .data
+            .cv
+        };
+        assert_eq!(cv(&measurement(2.0, 0.1)), Some(0.05));
+        assert_eq!(cv(&measurement(0.0, 0.1)), None);
+        assert_eq!(cv(&measurement(1e-310, 0.1)), None);
+    }
+
+    #[test]
+    fn flags_high_system_time_as_suspect() {
+        let measurement = |median: f64, user: f64, system: f64| Measurement {
+            user,
+            system,
+            ..measurement(median, 0.01)
+        };
+        let baseline = measurement(1.0, 0.9, 0.1);
+        // a busy runner: slower, and mostly in the kernel
+        let dev = measurement(1.5, 0.5, 0.7);
+
+        let opts = CalculateOptions {
+            max_system_ratio: Some(0.5),
+            ..CalculateOptions::default()
+        };
+        let calcs = calculate(
+            "test_metric",
+            &dev,
+            &baseline,
+            &Thresholds::default(),
+            &opts,
+        );
+        assert!(calcs[0].regression && calcs[0].suspect);
+        assert!(!calcs[0].gates());
+
+        let calcs = calculate(
+            "test_metric",
+            &baseline,
+            &baseline,
+            &Thresholds::default(),
+            &opts,
+        );
+        assert!(!calcs[0].suspect);
+        // no cpu time at all isn't suspicious
+        assert_eq!(system_ratio(&measurement(1.0, 0.0, 0.0)), 0.0);
+
+        let opts = CalculateOptions {
+            max_system_ratio: Some(1.5),
+            ..CalculateOptions::default()
+        };
+        assert!(matches!(
+            opts.validate(),
+            Err(CalculateError::BadThresholdErr(..))
+        ));
+    }
+
+    #[test]
+    fn welch_test_requires_significance() {
+        let baseline = sampled(vec![1.0, 1.1, 0.9, 1.05, 0.95]);
+        // 10% slower on average, but far too noisy to be significant
+        let noisy = sampled(vec![0.6, 1.6, 0.8, 1.5, 1.0]);
+        // 20% slower, consistently
+        let slow = sampled(vec![1.2, 1.3, 1.1, 1.25, 1.15]);
+
+        let opts = CalculateOptions {
+            statistic: Statistic::Mean,
+            test: ComparisonTest::Welch,
+            ..CalculateOptions::default()
+        };
+        let run = |dev: &Measurement| {
+            calculate("test_metric", dev, &baseline, &Thresholds::default(), &opts).remove(0)
+        };
+
+        let noisy_calc = run(&noisy);
+        assert!(!noisy_calc.regression);
+        assert!(noisy_calc.data.p_value.unwrap() > 0.05);
+
+        let slow_calc = run(&slow);
+        assert!(slow_calc.regression);
+        assert!(slow_calc.data.p_value.unwrap() < 0.05);
+
+        // the slow dev mean falls well outside the baseline's interval
+        assert!(slow_calc.data.ci_low.unwrap() < 1.0);
+        assert!(slow_calc.data.ci_high.unwrap() < slow_calc.data.dev);
+    }
+
+    #[test]
+    fn bootstrap_test_requires_the_whole_interval_above_zero() {
+        let baseline = sampled(vec![1.0, 1.1, 0.9, 1.05, 0.95]);
+        let noisy = sampled(vec![0.6, 1.6, 0.8, 1.5, 1.0]);
+        let slow = sampled(vec![1.2, 1.3, 1.1, 1.25, 1.15]);
+
+        let opts = CalculateOptions {
+            statistic: Statistic::Mean,
+            test: ComparisonTest::Bootstrap,
+            bootstrap_iterations: 2000,
+            ..CalculateOptions::default()
+        };
+        let run = |dev: &Measurement| {
+            calculate("test_metric", dev, &baseline, &Thresholds::default(), &opts).remove(0)
+        };
+
+        let noisy_calc = run(&noisy);
+        assert!(!noisy_calc.regression);
+        assert!(noisy_calc.data.diff_ci_low.unwrap() < 0.0);
+
+        let slow_calc = run(&slow);
+        assert!(slow_calc.regression);
+        assert!(slow_calc.data.diff_ci_low.unwrap() > 0.0);
+        assert_eq!(slow_calc.data.p_value, None);
+
+        // the stddev calculation is still a plain ratio
+        let stddev_calc = calculate(
+            "test_metric",
+            &slow,
+            &baseline,
+            &Thresholds::default(),
+            &opts,
+        )
+        .remove(1);
+        assert_eq!(stddev_calc.data.diff_ci_low, None);
+
+        let opts = CalculateOptions {
+            bootstrap_iterations: 0,
+            ..opts
+        };
+        assert!(matches!(
+            opts.validate(),
+            Err(CalculateError::BadBootstrapIterationsErr(0))
+        ));
+    }
+
+    #[test]
+    fn mann_whitney_test_flags_skewed_regressions() {
+        // one very slow baseline run drags its mean up past dev's, but dev
+        // is slower in every other run
+        let baseline = sampled(vec![1.0, 1.02, 0.98, 1.01, 0.99, 1.03, 0.97, 9.0]);
+        let dev = sampled(vec![1.1, 1.12, 1.08, 1.11, 1.09, 1.13, 1.07, 1.14]);
+
+        let run = |test| {
+            calculate(
+                "test_metric",
+                &dev,
+                &baseline,
+                &Thresholds::default(),
+                &CalculateOptions {
+                    statistic: Statistic::Mean,
+                    test,
+                    ..CalculateOptions::default()
+                },
+            )
+            .remove(0)
+        };
+
+        assert!(!run(ComparisonTest::Welch).regression);
+        let calc = run(ComparisonTest::MannWhitney);
+        assert!(calc.regression);
+        assert_eq!(calc.data.u_statistic, Some(56.0));
+        assert!(calc.data.p_value.unwrap() < 0.05);
+        assert_eq!(
+            "mann-whitney".parse::<ComparisonTest>(),
+            Ok(ComparisonTest::MannWhitney)
+        );
+    }
+
+    #[test]
+    fn corrects_alpha_for_many_comparisons() {
+        let p_values = [0.01, 0.036, 0.02, 0.20];
+        assert_eq!(Correction::None.alpha(0.05, &p_values), 0.05);
+        assert_eq!(Correction::Bonferroni.alpha(0.05, &p_values), 0.0125);
+        // 0.036 is the largest p-value under its step, of 3/4 * 0.05
+        assert!((Correction::Bh.alpha(0.05, &p_values) - 0.0375).abs() < 1e-12);
+        assert_eq!(Correction::Bh.alpha(0.05, &[0.5, 0.9]), 0.025);
+        // a p-value exactly at its step passes the step and the verdict alike
+        let boundary = Correction::Bh.alpha(0.05, &[0.025, 0.9]);
+        assert_eq!(boundary, 0.025);
+        assert!(significant(0.025, boundary));
+        assert!(!significant(0.9, boundary));
+        assert_eq!(Correction::Bonferroni.alpha(0.05, &[]), 0.05);
+    }
+
+    #[test]
+    fn correction_cuts_false_positives_on_noise() {
+        // dev and baseline are drawn from the same distribution, so every
+        // regression found is a false positive
+        let mut seed: u64 = 42;
+        let mut noise = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            1.0 + ((seed >> 33) as f64 / (1u64 << 31) as f64 - 0.5) / 10.0
+        };
+        let mut measurement = || sampled((0..10).map(|_| noise()).collect());
+        let paths: Vec<PathBuf> = (0..200)
+            .flat_map(|i| {
+                vec![
+                    PathBuf::from(format!("baseline_parse_project_{:03}.json", i)),
+                    PathBuf::from(format!("dev_parse_project_{:03}.json", i)),
+                ]
+            })
+            .collect();
+        let ms: Vec<Measurement> = paths.iter().map(|_| measurement()).collect();
+        let measurements: Vec<(&PathBuf, &Measurement)> = paths.iter().zip(ms.iter()).collect();
+
+        let false_positives = |correction| {
+            let opts = CalculateOptions {
+                test: ComparisonTest::Welch,
+                correction,
+                ..CalculateOptions::default()
+            };
+            let calculations = calculate_regressions(&measurements, &opts)
+                .unwrap()
+                .calculations;
+            let tested = calculations.iter().find_map(|c| c.data.alpha);
+            let count = calculations.iter().filter(|c| c.regression).count();
+            (count, tested)
+        };
+
+        let (uncorrected, alpha) = false_positives(Correction::None);
+        assert!(uncorrected > 0);
+        assert_eq!(alpha, Some(0.05));
+        let (corrected, alpha) = false_positives(Correction::Bonferroni);
+        assert!(corrected < uncorrected);
+        assert_eq!(alpha, Some(0.05 / 200.0));
+        assert!(false_positives(Correction::Bh).0 < uncorrected);
+    }
+
+    #[test]
+    fn trimming_ignores_a_single_outlier() {
+        let baseline = sampled(vec![
+            1.0, 1.01, 0.99, 1.02, 0.98, 1.0, 1.01, 0.99, 1.02, 0.98,
+        ]);
+        // one hiccup that took ten times as long
+        let dev = sampled(vec![
+            1.0, 1.01, 0.99, 1.02, 0.98, 1.0, 1.01, 0.99, 1.02, 10.0,
+        ]);
+
+        let regressed = |trim| {
+            let opts = CalculateOptions {
+                statistic: Statistic::Mean,
+                trim,
+                ..CalculateOptions::default()
+            };
+            calculate(
+                "test_metric",
+                &dev,
+                &baseline,
+                &Thresholds::default(),
+                &opts,
+            )[0]
+            .regression
+        };
+
+        assert!(regressed(0.0));
+        assert!(!regressed(0.1));
+    }
+
+    #[test]
+    fn ignores_changes_under_the_absolute_floor() {
+        let measurement = |median: f64| measurement(median, 0.0001);
+        let baseline = measurement(0.005);
+        let regressed = |dev: f64, min_abs_delta| {
+            let opts = CalculateOptions {
+                min_abs_delta,
+                ..CalculateOptions::default()
+            };
+            let c = calculate(
+                "test_metric",
+                &measurement(dev),
+                &baseline,
+                &Thresholds::default(),
+                &opts,
+            )
+            .remove(0);
+            assert_eq!(c.data.min_abs_delta, min_abs_delta);
+            c.regression
+        };
+
+        // 10% slower, but only by half a millisecond
+        assert!(regressed(0.0055, 0.0));
+        assert!(!regressed(0.0055, 0.001));
+        assert!(regressed(0.007, 0.001));
+    }
+
+    #[test]
+    fn flags_noisy_measurements() {
+        let measurement = |median: f64, stddev: f64| Measurement {
+            median,
+            ..measurement(1.00, stddev)
+        };
+        let baseline = measurement(1.00, 0.05);
+        let noisy = |dev: &Measurement, max_cv| {
+            let opts = CalculateOptions {
+                max_cv,
+                ..CalculateOptions::default()
+            };
+            calculate("test_metric", dev, &baseline, &Thresholds::default(), &opts)
+                .iter()
+                .map(|c| c.noisy)
+                .collect::<Vec<bool>>()
+        };
+
+        // a 40% cv regression is reported, but flagged as noise
+        let dev = measurement(1.10, 0.40);
+        assert_eq!(noisy(&dev, Some(0.25)), vec![true, true]);
+        assert_eq!(noisy(&dev, None), vec![false, false]);
+        assert_eq!(
+            noisy(&measurement(1.10, 0.05), Some(0.25)),
+            vec![false, false]
+        );
+    }
+
+    #[test]
+    fn flags_failed_commands() {
+        let measurement = |exit_codes: Vec<Option<i32>>| Measurement {
+            exit_codes,
+            ..measurement(1.00, 0.05)
+        };
+        let baseline = measurement(vec![Some(0), Some(0)]);
+        let failed = |dev: &Measurement| {
+            calculate(
+                "test_metric",
+                dev,
+                &baseline,
+                &Thresholds::default(),
+                &CalculateOptions::default(),
+            )
+            .iter()
+            .map(|c| c.failed)
+            .collect::<Vec<bool>>()
+        };
+
+        // one bad exit, or one killed by a signal, taints the whole measurement
+        assert_eq!(
+            failed(&measurement(vec![Some(0), Some(1)])),
+            vec![true, true]
+        );
+        assert_eq!(failed(&measurement(vec![None, Some(0)])), vec![true, true]);
+        assert_eq!(
+            failed(&measurement(vec![Some(0), Some(0)])),
+            vec![false, false]
+        );
+        // results from before exit codes were recorded aren't failures
+        assert_eq!(failed(&measurement(vec![])), vec![false, false]);
+    }
+
+    #[test]
+    fn detects_5_percent_improvement() {
+        let dev = measurement(0.94, 1.00);
+
+        let baseline = measurement(1.00, 1.00);
+
+        let calculations = calculate(
+            "test_metric",
+            &dev,
+            &baseline,
+            &Thresholds::default(),
+            &CalculateOptions::default(),
+        );
+        let improvements: Vec<&Calculation> = calculations
+            .iter()
+            .filter(|calc| calc.improvement)
+            .collect();
+
+        // expect one improvement for median, and no regressions at all
+        assert_eq!(improvements.len(), 1);
+        assert_eq!(improvements[0].metric, "median_test_metric");
+        assert!(calculations.iter().all(|calc| !calc.regression));
+    }
+
+    #[test]
+    fn mirrors_the_threshold_as_its_reciprocal() {
+        assert!(is_improvement(0.95, 1.05));
+        assert!(!is_improvement(0.96, 1.05));
+        // twice as slow regresses, so twice as fast improves
+        assert!(is_improvement(0.49, 2.0));
+        assert!(!is_improvement(0.5, 2.0));
+        assert!(is_improvement(0.3, 3.0));
+    }
+
+    #[test]
+    fn inverts_higher_is_better_metrics() {
+        let measurement = |median: f64| Measurement {
+            min: 1.00,
+            max: 1.00,
+            ..measurement(median, 1.00)
+        };
+        let baseline = measurement(100.0);
+        let opts = CalculateOptions {
+            directions: vec![("throughput".to_owned(), Direction::HigherIsBetter)]
+                .into_iter()
+                .collect(),
+            ..CalculateOptions::default()
+        };
+        let verdict = |metric: &str, median: f64| {
+            let c = calculate(
+                metric,
+                &measurement(median),
+                &baseline,
+                &Thresholds::default(),
+                &opts,
+            )
+            .remove(0);
+            (c.regression, c.improvement)
+        };
+        let directions: Vec<Direction> = calculate(
+            "throughput_project",
+            &measurement(90.0),
+            &baseline,
+            &Thresholds::default(),
+            &opts,
+        )
+        .iter()
+        .map(|c| c.direction)
+        .collect();
+
+        // the direction is recorded, but a stddev is always lower-is-better
+        assert_eq!(
+            directions,
+            vec![Direction::HigherIsBetter, Direction::LowerIsBetter]
+        );
+        // fewer models per second is a regression
+        assert_eq!(verdict("throughput_project", 90.0), (true, false));
+        assert_eq!(verdict("throughput_project", 110.0), (false, true));
+        // metrics without a direction are still lower-is-better
+        assert_eq!(verdict("parse_project", 90.0), (false, true));
+        assert_eq!(verdict("parse_project", 110.0), (true, false));
+    }
+
+    #[test]
+    fn floors_zero_baseline_stddev() {
+        let measurement = |stddev: f64| measurement(1.00, stddev);
+        let stddev_calc = |dev: &Measurement, baseline: &Measurement| {
+            calculate(
+                "test_metric",
+                dev,
+                baseline,
+                &Thresholds::default(),
+                &CalculateOptions::default(),
+            )
+            .remove(1)
+        };
+
+        // a normal baseline is compared by ratio
+        let calc = stddev_calc(&measurement(0.011), &measurement(0.01));
+        assert_eq!(calc.rule, Rule::Ratio);
+        assert!(!calc.regression);
+
+        // a 1% stddev is within the noise floor of a perfectly stable baseline
+        let calc = stddev_calc(&measurement(0.01), &measurement(0.0));
+        assert_eq!(calc.rule, Rule::StddevFloor);
+        assert!((calc.data.difference.unwrap() - 0.5).abs() < 1e-9);
+        assert!((calc.data.baseline - 0.02).abs() < 1e-9);
+        assert!((calc.data.pct_change.unwrap() - -50.0).abs() < 1e-9);
+        assert!(!calc.regression);
+
+        // but a 5% stddev is still flagged
+        let calc = stddev_calc(&measurement(0.05), &measurement(0.0));
+        assert_eq!(calc.rule, Rule::StddevFloor);
+        assert!(calc.regression);
+
+        // unless it's within the absolute floor of the floored stddev
+        let opts = CalculateOptions {
+            min_abs_delta: 0.04,
+            ..CalculateOptions::default()
+        };
+        let calc = calculate(
+            "test_metric",
+            &measurement(0.05),
+            &measurement(0.0),
+            &Thresholds::default(),
+            &opts,
+        )
+        .remove(1);
+        assert_eq!(calc.rule, Rule::StddevFloor);
+        assert!(!calc.regression);
+    }
+
+    #[test]
+    fn leaves_no_difference_from_a_zero_baseline() {
+        let calculations = calculate(
+            "test_metric",
+            &measurement(0.1, 0.0),
+            &measurement(0.0, 0.0),
+            &Thresholds::default(),
+            &CalculateOptions::default(),
+        );
+
+        // anything over nothing regresses, and nothing over nothing doesn't
+        assert_eq!(calculations[0].data.difference, None);
+        assert!(calculations[0].regression);
+        assert_eq!(calculations[1].data.difference, None);
+        assert!(!calculations[1].regression);
+        assert!(serde_json::to_string(&calculations)
+            .unwrap()
+            .contains("\"difference\":null"));
+    }
+
+    #[test]
+    fn computes_percent_change() {
+        assert_eq!(pct_change(1.5, 1.0), Some(50.0));
+        assert_eq!(pct_change(0.5, 2.0), Some(-75.0));
+        assert_eq!(pct_change(1.0, 0.0), None);
+        assert_eq!(pct_change(1.0, 1e-310), None);
+    }
+
+    #[test]
+    fn computes_percentiles_missing_from_old_results() {
+        let old: Measurement = serde_json::from_str(
+            r#"{"command":"dbt parse","mean":1.5,"stddev":0.5,"median":1.5,"user":1.0,"system":0.5,"min":1.0,"max":2.0,"times":[1.0,2.0]}"#,
+        )
+        .unwrap();
+        assert_eq!((old.p95, old.p99), (None, None));
+        assert!((Statistic::P95.of(&old) - 1.95).abs() < 1e-9);
+
+        let new = old.clone().with_percentiles();
+        assert_eq!(Statistic::P95.of(&new), Statistic::P95.of(&old));
+        assert!((new.p99.unwrap() - 1.99).abs() < 1e-9);
+
+        // nothing to compute from, so the slowest run stands in
+        let no_times = Measurement {
+            times: vec![],
+            ..old
+        };
+        assert_eq!(Statistic::P99.of(&no_times), 2.0);
+    }
+
+    #[test]
+    fn requires_exactly_one_measurement_per_file() {
+        let m = measurement(1.00, 1.00);
+        let path = Path::new("dummy/path/dev_parse_project.json");
+
+        let count = |n: usize| {
+            let ms = Measurements {
+                results: vec![m.clone(); n],
+            };
+            match single_measurement(path, &ms) {
+                Ok(found) => {
+                    assert_eq!(found, &m);
+                    1
+                }
+                Err(CalculateError::BadMeasurementCountErr(p, found)) => {
+                    assert_eq!(p, path);
+                    found
+                }
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        };
+
+        assert_eq!(count(0), 0);
+        assert_eq!(count(1), 1);
+        assert_eq!(count(3), 3);
+    }
+
+    #[test]
+    fn rejects_non_finite_measurements() {
+        let m = Measurement {
+            times: vec![1.00],
+            ..measurement(1.00, 1.00)
+        };
+        let path = Path::new("dummy/path/dev_parse_project.json");
+        assert!(m.validate(path).is_ok());
+
+        let corrupt = [
+            Measurement {
+                mean: f64::NAN,
+                ..m.clone()
+            },
+            Measurement {
+                stddev: f64::INFINITY,
+                ..m.clone()
+            },
+            Measurement {
+                times: vec![1.00, f64::NEG_INFINITY],
+                ..m.clone()
+            },
+        ];
+        let fields: Vec<String> = corrupt
+            .iter()
+            .map(|m| match m.validate(path) {
+                Err(CalculateError::NonFiniteErr(p, field, _)) => {
+                    assert_eq!(p, path);
+                    field
+                }
+                x => panic!("expected NonFiniteErr, got {:?}", x),
+            })
+            .collect();
+        assert_eq!(fields, vec!["mean", "stddev", "times"]);
+
+        // NaN isn't valid json, so a file holding it fails to load at all
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let file = dir.join("dev_parse_project.json");
+        fs::write(
+            &file,
+            serde_json::to_string(&Measurements {
+                results: vec![m.clone()],
+            })
+            .unwrap()
+            .replace(r#""mean":1.0"#, r#""mean":NaN"#),
+        )
+        .unwrap();
+        let result = measurements_from_files(&dir, &Filter::default(), false);
+        match result {
+            Err(CalculateError::BadJSONErr(p, _)) => assert_eq!(p, file),
+            x => panic!("expected BadJSONErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn names_the_malformed_results_file() {
+        let m = measurement(1.00, 1.00);
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let good = dir.join("baseline_parse_project.json");
+        let bad = dir.join("dev_parse_project.json");
+        fs::write(
+            &good,
+            serde_json::to_string(&Measurements { results: vec![m] }).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            &bad,
+            r#"{"results": [{"command": "some command", "mean": 1.0}]}"#,
+        )
+        .unwrap();
+
+        let result = measurements_from_files(&dir, &Filter::default(), false);
+        match result {
+            Err(e @ CalculateError::BadJSONErr(_, _)) => {
+                let msg = format!("{}", e);
+                assert!(msg.contains(&format!("Filepath: {}", bad.to_string_lossy())));
+                assert!(msg.contains("missing field `stddev`"));
+            }
+            x => panic!("expected BadJSONErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn diffs_two_branches() {
+        let measurement = |median: f64| Measurement {
+            min: 1.00,
+            max: 1.00,
+            ..measurement(median, 0.1)
+        };
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let files = [
+            ("0.19.latest_parse_moved.json", 1.0),
+            ("0.20.latest_parse_moved.json", 1.5),
+            ("0.19.latest_parse_steady.json", 1.0),
+            ("0.20.latest_parse_steady.json", 1.05),
+            ("0.19.latest_parse_removed.json", 1.0),
+            ("0.20.latest_parse_added.json", 1.0),
+        ];
+        for (name, median) in files.iter() {
+            let ms = Measurements {
+                results: vec![measurement(*median)],
+            };
+            fs::write(dir.join(name), serde_json::to_string(&ms).unwrap()).unwrap();
+        }
+
+        let diffs = diff(
+            &dir,
+            "0.19.latest",
+            "0.20.latest",
+            Statistic::Median,
+            &Filter::default(),
+        );
+        let missing = diff(
+            &dir,
+            "0.18.latest",
+            "0.20.latest",
+            Statistic::Median,
+            &Filter::default(),
+        );
+
+        let summary: Vec<(&str, Option<f64>, Option<f64>, bool)> = diffs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|d| (&d.project[..], d.from, d.to, d.moved))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("added", None, Some(1.0), false),
+                ("moved", Some(1.0), Some(1.5), true),
+                ("removed", Some(1.0), None, false),
+                ("steady", Some(1.0), Some(1.05), false),
+            ]
+        );
+
+        match missing {
+            Err(CalculateError::NoBranchResultsErr(branch, _)) => assert_eq!(branch, "0.18.latest"),
+            x => panic!("expected NoBranchResultsErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn reports_every_bad_results_file() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let m = measurement(1.00, 1.00);
+        let write = |name: &str, ms: Vec<Measurement>| {
+            let json = serde_json::to_string(&Measurements { results: ms }).unwrap();
+            fs::write(dir.join(name), json).unwrap();
+        };
+        write("baseline_parse_good.json", vec![m.clone()]);
+        write("baseline_parse_two.json", vec![m.clone(), m]);
+        fs::write(dir.join("baseline_parse_truncated.json"), "{\"results\": [").unwrap();
+
+        let checked = validate_results(&dir, false);
+
+        let (count, problems) = checked.unwrap();
+        assert_eq!(count, 3);
+        let mut names: Vec<&str> = problems
+            .iter()
+            .map(|e| match e {
+                CalculateError::BadJSONErr(..) => "BadJSONErr",
+                CalculateError::BadMeasurementCountErr(..) => "BadMeasurementCountErr",
+                x => panic!("unexpected problem {:?}", x),
+            })
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["BadJSONErr", "BadMeasurementCountErr"]);
+    }
+
+    #[test]
+    fn reads_gzipped_results() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let m = measurement(1.00, 1.00);
+        let json = serde_json::to_string(&Measurements {
+            results: vec![m.clone()],
+        })
+        .unwrap();
+
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        fs::write(dir.join("baseline_parse_project.json"), &json).unwrap();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(json.as_bytes()).unwrap();
+        fs::write(dir.join("dev_parse_project.json.gz"), gz.finish().unwrap()).unwrap();
+
+        let result = measurements_from_files(&dir, &Filter::default(), false);
+
+        let mut loaded = result.unwrap();
+        loaded.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.iter().all(|(_, ms)| ms.results == vec![m.clone()]));
+        assert_eq!(
+            key_from_path(&loaded[1].0),
+            Some(MetricKey::new("project", "parse"))
+        );
+    }
+
+    #[test]
+    fn skips_files_that_are_not_results() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        fs::create_dir_all(dir.join("nested.json")).unwrap();
+        let names = [
+            "baseline_parse_project.json",
+            "dev_parse_project.json.gz",
+            "README.md",
+            ".DS_Store",
+            "dev.metadata",
+            "notes.json.txt",
+            "backup.xjson",
+        ];
+        for name in names.iter() {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let found = results_files(&dir, false);
+
+        let mut found: Vec<String> = found
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec!["baseline_parse_project.json", "dev_parse_project.json.gz"]
+        );
+    }
+
+    #[test]
+    fn reads_subdirectories_when_recursive() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        fs::create_dir_all(dir.join("1.x").join("1.0")).unwrap();
+        fs::write(dir.join("baseline_parse_top.json"), "").unwrap();
+        fs::write(dir.join("1.x").join("baseline_parse_one.json"), "").unwrap();
+        fs::write(
+            dir.join("1.x").join("1.0").join("baseline_parse_two.json"),
+            "",
+        )
+        .unwrap();
+
+        let names = |recursive| {
+            let mut names: Vec<String> = results_files(&dir, recursive)
+                .unwrap()
+                .iter()
+                .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        let (flat, nested) = (names(false), names(true));
+
+        assert_eq!(flat, vec!["baseline_parse_top.json"]);
+        assert_eq!(
+            nested,
+            vec![
+                "baseline_parse_one.json",
+                "baseline_parse_top.json",
+                "baseline_parse_two.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_two_directories() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let write = |side: &str, name: &str, median: f64| {
+            let m = measurement(median, 0.1);
+            fs::create_dir_all(dir.join(side)).unwrap();
+            let json = serde_json::to_string(&Measurements { results: vec![m] }).unwrap();
+            fs::write(dir.join(side).join(name), json).unwrap();
+        };
+        write("a", "main_parse_project.json", 1.0);
+        write("a", "main_parse_removed.json", 1.0);
+        write("b", "feature_parse_project.json", 1.2);
+
+        let comparison = compare(&dir.join("a"), &dir.join("b"), &CalculateOptions::default());
+
+        let comparison = comparison.unwrap();
+        assert_eq!(
+            comparison.calculations[0].metric,
+            "median_parse_project.json"
+        );
+        assert!(comparison.calculations[0].regression);
+        assert_eq!(comparison.missing, vec![MetricKey::new("removed", "parse")]);
+    }
+
+    #[test]
+    fn compares_one_branch_of_a_mixed_directory() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let write = |name: &str, median: f64| {
+            let json = serde_json::to_string(&Measurements {
+                results: vec![measurement(median, 0.1)],
+            })
+            .unwrap();
+            fs::write(dir.join(name), json).unwrap();
+        };
+        write("baseline_parse_project.json", 1.0);
+        write("dev_parse_project.json", 1.2);
+
+        let mixed = compare(&dir, &dir, &CalculateOptions::default());
+        let opts = |reference: &str, candidate: &str| CalculateOptions {
+            reference_branch: Some(reference.to_owned()),
+            candidate_branch: Some(candidate.to_owned()),
+            ..CalculateOptions::default()
+        };
+        let picked = compare(&dir, &dir, &opts("baseline", "dev"));
+        let unknown = compare(&dir, &dir, &opts("baseline", "main"));
+
+        assert!(matches!(
+            mixed,
+            Err(CalculateError::MixedBranchesErr(_, branches)) if branches == ["baseline", "dev"]
+        ));
+        assert!(picked.unwrap().calculations[0].regression);
+        assert!(matches!(
+            unknown,
+            Err(CalculateError::NoBranchResultsErr(branch, _)) if branch == "main"
+        ));
+    }
+
+    #[test]
+    fn compares_samples_given_directly() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let measurement = |median: f64| measurement(median, 0.1);
+        let json = |median| {
+            serde_json::to_string(&Measurements {
+                results: vec![measurement(median)],
+            })
+            .unwrap()
+        };
+        fs::write(dir.join("main_parse_project.json"), json(1.0)).unwrap();
+        fs::write(dir.join("main_parse_other.json"), json(1.0)).unwrap();
+
+        let stdin = Path::new("<stdin>");
+        let opts = |projects: &[&str]| CalculateOptions {
+            filter: Filter {
+                projects: projects.iter().map(|p| p.to_string()).collect(),
+                metrics: vec!["parse".to_owned()],
+                ..Filter::default()
+            },
+            ..CalculateOptions::default()
+        };
+        let compared = compare_samples(&dir, stdin, &json(1.2), &opts(&["project"]));
+        let unnamed = compare_samples(&dir, stdin, &json(1.2), &opts(&[]));
+        let empty = compare_samples(&dir, stdin, " \n", &opts(&["project"]));
+        let bad = compare_samples(&dir, stdin, "{\"results\": [", &opts(&["project"]));
+
+        // the other project isn't reported missing
+        let compared = compared.unwrap();
+        assert_eq!(compared.calculations[0].metric, "median_parse_project.json");
+        assert!(compared.calculations[0].regression);
+        assert_eq!(compared.missing, vec![]);
+        assert!(matches!(unnamed, Err(CalculateError::SamplesPairErr(0, 1))));
+        assert!(matches!(empty, Err(CalculateError::EmptySamplesErr(_))));
+        assert!(matches!(bad, Err(CalculateError::BadJSONErr(..))));
+    }
+
+    #[test]
+    fn rejects_non_positive_thresholds() {
+        let thresholds = ThresholdConfig {
+            global: Thresholds {
+                median: 0.0,
+                ..Thresholds::default()
+            },
+            ..ThresholdConfig::default()
+        };
+
+        let opts = CalculateOptions {
+            thresholds,
+            ..CalculateOptions::default()
+        };
+
+        match regressions(&PathBuf::from("dummy/path/"), &opts) {
+            Err(CalculateError::BadThresholdErr(name, t)) => {
+                assert_eq!(name, "median");
+                assert_eq!(t, 0.0);
+            }
+            x => panic!("expected BadThresholdErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn resolves_threshold_overrides() {
+        let overrides = toml::from_str(
+            r#"
+            [01_2000_simple_models.parse]
+            median = 1.10
+            "#,
+        )
+        .unwrap();
+        let config = ThresholdConfig {
+            global: Thresholds::default(),
+            overrides,
+        };
+
+        let key = MetricKey::from_run("parse_01_2000_simple_models.json");
+        assert_eq!(key, MetricKey::new("01_2000_simple_models", "parse"));
+        assert_eq!(
+            config.resolve(&key),
+            Thresholds {
+                median: 1.10,
+                stddev: 1.20
+            }
+        );
+        assert_eq!(
+            config.resolve(&MetricKey::new("01_2000_simple_models", "run")),
+            Thresholds::default()
+        );
+    }
+
+    #[test]
+    fn calculates_many_groups() {
+        let m = measurement(1.00, 1.00);
+
+        let paths: Vec<PathBuf> = (0..1000)
+            .flat_map(|i| {
+                vec![
+                    PathBuf::from(format!("baseline_parse_project_{:04}.json", i)),
+                    PathBuf::from(format!("dev_parse_project_{:04}.json", i)),
+                ]
+            })
+            .collect();
+        let measurements: Vec<(&PathBuf, &Measurement)> = paths.iter().map(|p| (p, &m)).collect();
+
+        let calculations = calculate_regressions(&measurements, &CalculateOptions::default())
+            .unwrap()
+            .calculations;
+
+        assert_eq!(calculations.len(), 2000);
+        assert!(calculations.iter().all(|c| !c.regression));
+        assert_eq!(calculations[0].metric, "median_parse_project_0000.json");
+        assert_eq!(calculations[1999].metric, "stddev_parse_project_0999.json");
+    }
+
+    #[test]
+    fn orders_calculations_by_project_then_metric() {
+        let m = measurement(1.00, 1.00);
+        let paths: Vec<PathBuf> = ["b", "a"]
+            .iter()
+            .flat_map(|project| {
+                ["run", "parse"].iter().flat_map(move |metric| {
+                    ["dev", "baseline"].iter().map(move |branch| {
+                        PathBuf::from(format!("{}_{}_{}.json", branch, metric, project))
+                    })
+                })
+            })
+            .collect();
+        let metrics = |paths: Vec<&PathBuf>| {
+            let measurements: Vec<(&PathBuf, &Measurement)> =
+                paths.into_iter().map(|p| (p, &m)).collect();
+            calculate_regressions(&measurements, &CalculateOptions::default())
+                .unwrap()
+                .calculations
+                .into_iter()
+                .map(|c| c.metric)
+                .collect::<Vec<String>>()
+        };
+
+        let forward = metrics(paths.iter().collect());
+        assert_eq!(
+            forward,
+            vec![
+                "median_parse_a.json",
+                "stddev_parse_a.json",
+                "median_run_a.json",
+                "stddev_run_a.json",
+                "median_parse_b.json",
+                "stddev_parse_b.json",
+                "median_run_b.json",
+                "stddev_run_b.json",
+            ]
+        );
+        assert_eq!(metrics(paths.iter().rev().collect()), forward);
+    }
+
+    #[test]
+    fn collects_pairs_missing_either_side() {
+        let m = measurement(1.00, 1.00);
+        let paths = [
+            PathBuf::from("baseline_parse_crashed.json"),
+            PathBuf::from("baseline_parse_project.json"),
+            PathBuf::from("dev_parse_project.json"),
+            PathBuf::from("dev_parse_unblessed.json"),
+        ];
+        let measurements: Vec<(&PathBuf, &Measurement)> = paths.iter().map(|p| (p, &m)).collect();
+
+        let comparison =
+            calculate_regressions(&measurements, &CalculateOptions::default()).unwrap();
+
+        assert_eq!(comparison.missing, vec![MetricKey::new("crashed", "parse")]);
+        assert_eq!(comparison.new, vec![MetricKey::new("unblessed", "parse")]);
+        assert_eq!(comparison.calculations.len(), 2);
+        assert!(comparison
+            .calculations
+            .iter()
+            .all(|c| c.metric.ends_with("_parse_project.json")));
+    }
+
+    #[test]
+    fn rejects_duplicate_pairs() {
+        let m = measurement(1.00, 1.00);
+        let paths = [
+            PathBuf::from("baseline_parse_project.json"),
+            PathBuf::from("dev_parse_project.json"),
+            PathBuf::from("dev_parse_project.json.gz"),
+        ];
+        let measurements: Vec<(&PathBuf, &Measurement)> = paths.iter().map(|p| (p, &m)).collect();
+
+        match calculate_regressions(&measurements, &CalculateOptions::default()) {
+            Err(CalculateError::DuplicateMetricErr(branch, project, metric)) => {
+                assert_eq!(
+                    (&branch[..], &project[..], &metric[..]),
+                    ("dev", "project", "parse")
+                )
+            }
+            x => panic!("expected DuplicateMetricErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn distribution_tests_need_times() {
+        let measurement = |times: Vec<f64>| Measurement {
+            min: 0.90,
+            max: 1.10,
+            times,
+            ..measurement(1.00, 0.10)
+        };
+        // a baseline written with only its summary statistics
+        let (baseline, dev) = (measurement(vec![]), measurement(vec![0.9, 1.0, 1.1]));
+        let paths = [
+            PathBuf::from("baseline_parse_project.json"),
+            PathBuf::from("dev_parse_project.json"),
+        ];
+        let measurements = vec![(&paths[0], &baseline), (&paths[1], &dev)];
+
+        for &test in [
+            ComparisonTest::Welch,
+            ComparisonTest::Bootstrap,
+            ComparisonTest::MannWhitney,
+        ]
+        .iter()
+        {
+            let opts = CalculateOptions {
+                test,
+                ..CalculateOptions::default()
+            };
+            match calculate_regressions(&measurements, &opts) {
+                Err(CalculateError::MissingTimesErr(branch, project, metric)) => assert_eq!(
+                    (&branch[..], &project[..], &metric[..]),
+                    ("baseline", "project", "parse")
+                ),
+                x => panic!("expected MissingTimesErr, got {:?}", x),
+            }
+        }
+        // the summary statistics are all the ratio test needs
+        assert!(calculate_regressions(&measurements, &CalculateOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn compares_dev_against_given_baselines() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let measurement = |median: f64| Measurement {
+            times: vec![median],
+            ..measurement(median, 0.1)
+        };
+        let write = |name: &str, m: &Measurement| {
+            let ms = Measurements {
+                results: vec![m.clone()],
+            };
+            fs::write(dir.join(name), serde_json::to_string(&ms).unwrap()).unwrap();
+        };
+        write("dev_parse_a.json", &measurement(1.5));
+        write("dev_parse_b.json", &measurement(1.0));
+        // ignored in favor of the given baselines
+        write("baseline_parse_a.json", &measurement(1.5));
+
+        let baselines = vec![
+            ("parse_a.json".to_owned(), measurement(1.0)),
+            ("parse_c.json".to_owned(), measurement(1.0)),
+        ];
+        let comparison = regressions_against(&dir, &baselines, &CalculateOptions::default());
+        let no_dev = regressions_against(
+            &dir,
+            &baselines,
+            &CalculateOptions {
+                filter: Filter {
+                    projects: vec!["c".to_owned()],
+                    ..Filter::default()
+                },
+                ..CalculateOptions::default()
+            },
+        );
+
+        let comparison = comparison.unwrap();
+        assert_eq!(comparison.calculations[0].metric, "median_parse_a.json");
+        assert!(comparison.calculations[0].regression);
+        assert_eq!(comparison.new, vec![MetricKey::new("b", "parse")]);
+        assert_eq!(comparison.missing, vec![MetricKey::new("c", "parse")]);
+        assert!(matches!(
+            no_dev,
+            Err(CalculateError::NoBranchResultsErr(..))
+        ));
+    }
+
+    #[test]
+    fn compares_a_single_pair() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let measurement = |median: f64| Measurement {
+            times: vec![median],
+            ..measurement(median, 0.1)
+        };
+        let write = |name: &str, m: &Measurement| {
+            let ms = Measurements {
+                results: vec![m.clone()],
+            };
+            fs::write(dir.join(name), serde_json::to_string(&ms).unwrap()).unwrap();
+        };
+        write("feature_parse_a.json", &measurement(1.5));
+        write("baseline_parse_a.json", &measurement(1.0));
+        // not compared until it's measured
+        write("baseline_parse_b.json", &measurement(1.0));
+        write("feature_parse_c.json", &measurement(1.0));
+
+        let opts = CalculateOptions::default();
+        let a = pair_regressions(&dir, "feature", "parse_a.json", &opts);
+        let b = pair_regressions(&dir, "feature", "parse_b.json", &opts);
+        let c = pair_regressions(&dir, "feature", "parse_c.json", &opts);
+
+        let a = a.unwrap();
+        assert_eq!(a.len(), 2);
+        assert_eq!(a[0].metric, "median_parse_a.json");
+        assert!(a[0].regression);
+        assert_eq!(b.unwrap(), vec![]);
+        assert_eq!(c.unwrap(), vec![]);
+    }
+
+    #[test]
+    fn selects_requested_baseline_branch() {
+        let measurement = |median: f64| Measurement {
+            min: 1.00,
+            max: 1.00,
+            ..measurement(median, 1.00)
+        };
+        let (old, latest, dev) = (measurement(1.0), measurement(2.0), measurement(1.5));
+        let paths = [
+            PathBuf::from("0.20.latest_parse_project.json"),
+            PathBuf::from("baseline_parse_project.json"),
+            PathBuf::from("dev_parse_project.json"),
+        ];
+        let measurements = vec![(&paths[0], &old), (&paths[1], &latest), (&paths[2], &dev)];
+
+        let opts = CalculateOptions {
+            baseline_branch: Some("0.20.latest".to_owned()),
+            ..CalculateOptions::default()
+        };
+        let calculations = calculate_regressions(&measurements, &opts)
+            .unwrap()
+            .calculations;
+        assert_eq!(calculations[0].data.baseline, 1.0);
+        assert!(calculations[0].regression);
+
+        let opts = CalculateOptions {
+            baseline_branch: Some("0.19.latest".to_owned()),
+            ..CalculateOptions::default()
+        };
+        match calculate_regressions(&measurements, &opts) {
+            Err(CalculateError::BaselineBranchNotFoundErr(branch, _)) => {
+                assert_eq!(branch, "0.19.latest")
+            }
+            x => panic!("expected BaselineBranchNotFoundErr, got {:?}", x),
+        }
     }
 }
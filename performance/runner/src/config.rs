@@ -0,0 +1,201 @@
+use crate::calculate::{
+    CalculateOptions, ComparisonTest, Correction, Direction, Statistic, ThresholdConfig, Thresholds,
+};
+use crate::exceptions::{CalculateError, IOError};
+use crate::filter::Filter;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The config file read from the working directory when `--config` isn't given.
+pub const DEFAULT_CONFIG_FILE: &str = "runner.toml";
+
+// Settings that can be kept in a toml file instead of being passed as flags
+// on every run. The same type holds the settings given on the command line,
+// so the two can be merged with `or`: flags take precedence over the file,
+// and anything set in neither falls back to the usual defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub projects_dir: Option<PathBuf>,
+    pub results_dir: Option<PathBuf>,
+    pub out_dir: Option<PathBuf>,
+    pub median_threshold: Option<f64>,
+    pub stddev_threshold: Option<f64>,
+    // toml file of per-project, per-metric threshold overrides
+    pub thresholds: Option<PathBuf>,
+    // toml file of per-project, per-metric accepted regressions
+    pub accepted: Option<PathBuf>,
+    pub statistic: Option<Statistic>,
+    pub test: Option<ComparisonTest>,
+    pub alpha: Option<f64>,
+    pub correction: Option<Correction>,
+    pub baseline_branch: Option<String>,
+    pub projects: Vec<String>,
+    pub exclude_projects: Vec<String>,
+    pub metrics: Vec<String>,
+    // metrics that aren't lower-is-better, as a table of metric name to direction
+    pub directions: HashMap<String, Direction>,
+    // env vars set for every measured command, under any a project sets itself
+    pub env: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn from_toml_file(path: &Path) -> Result<Self, CalculateError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| IOError::BadFileContentsErr(path.to_path_buf(), Some(e)))?;
+
+        toml::from_str(&contents)
+            .map_err(|e| CalculateError::BadTOMLErr(path.to_path_buf(), Some(e)))
+    }
+
+    // Reads the config file at `path`, which must exist. Without a path,
+    // `runner.toml` is read if there is one, otherwise nothing is configured.
+    pub fn load(path: Option<&Path>) -> Result<Self, CalculateError> {
+        match path {
+            Some(p) => Config::from_toml_file(p),
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                Config::from_toml_file(Path::new(DEFAULT_CONFIG_FILE))
+            }
+            None => Ok(Config::default()),
+        }
+    }
+
+    // Every setting from `self`, with anything unset taken from `fallback`.
+    pub fn or(self, fallback: Config) -> Config {
+        let or_vec =
+            |v: Vec<String>, fallback: Vec<String>| if v.is_empty() { fallback } else { v };
+        Config {
+            projects_dir: self.projects_dir.or(fallback.projects_dir),
+            results_dir: self.results_dir.or(fallback.results_dir),
+            out_dir: self.out_dir.or(fallback.out_dir),
+            median_threshold: self.median_threshold.or(fallback.median_threshold),
+            stddev_threshold: self.stddev_threshold.or(fallback.stddev_threshold),
+            thresholds: self.thresholds.or(fallback.thresholds),
+            accepted: self.accepted.or(fallback.accepted),
+            statistic: self.statistic.or(fallback.statistic),
+            test: self.test.or(fallback.test),
+            alpha: self.alpha.or(fallback.alpha),
+            correction: self.correction.or(fallback.correction),
+            baseline_branch: self.baseline_branch.or(fallback.baseline_branch),
+            projects: or_vec(self.projects, fallback.projects),
+            exclude_projects: or_vec(self.exclude_projects, fallback.exclude_projects),
+            metrics: or_vec(self.metrics, fallback.metrics),
+            directions: if self.directions.is_empty() {
+                fallback.directions
+            } else {
+                self.directions
+            },
+            env: if self.env.is_empty() {
+                fallback.env
+            } else {
+                self.env
+            },
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        Filter {
+            projects: self.projects.clone(),
+            exclude_projects: self.exclude_projects.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    // Directories have no sensible default, so one of the two sources has to name it.
+    pub fn require(value: &Option<PathBuf>, name: &str) -> Result<PathBuf, CalculateError> {
+        value
+            .clone()
+            .ok_or_else(|| CalculateError::MissingSettingErr(name.to_owned()))
+    }
+
+    // Everything that controls how measurements are compared, with defaults
+    // for any setting that wasn't configured. Threshold overrides are read
+    // from their own file if one was named.
+    pub fn calculate_options(&self) -> Result<CalculateOptions, CalculateError> {
+        let defaults = CalculateOptions::default();
+        let global = Thresholds {
+            median: self
+                .median_threshold
+                .unwrap_or(defaults.thresholds.global.median),
+            stddev: self
+                .stddev_threshold
+                .unwrap_or(defaults.thresholds.global.stddev),
+        };
+        let thresholds = match &self.thresholds {
+            Some(path) => ThresholdConfig::from_toml_file(path, global)?,
+            None => ThresholdConfig {
+                global,
+                ..ThresholdConfig::default()
+            },
+        };
+
+        Ok(CalculateOptions {
+            thresholds,
+            statistic: self.statistic.unwrap_or(defaults.statistic),
+            test: self.test.unwrap_or(defaults.test),
+            alpha: self.alpha.unwrap_or(defaults.alpha),
+            correction: self.correction.unwrap_or(defaults.correction),
+            baseline_branch: self.baseline_branch.clone(),
+            filter: self.filter(),
+            directions: self.directions.clone(),
+            ..defaults
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_beats_config_beats_defaults() {
+        let file: Config = toml::from_str(
+            r#"
+            results_dir = "results/"
+            median_threshold = 1.10
+            statistic = "mean"
+            projects = ["a", "b"]
+
+            [directions]
+            throughput = "higher_is_better"
+            "#,
+        )
+        .unwrap();
+        let cli = Config {
+            median_threshold: Some(1.20),
+            projects: vec!["c".to_owned()],
+            ..Config::default()
+        };
+
+        let merged = cli.or(file);
+        let opts = merged.calculate_options().unwrap();
+
+        // from the command line
+        assert_eq!(opts.thresholds.global.median, 1.20);
+        assert_eq!(opts.filter.projects, vec!["c"]);
+        // from the config file
+        assert_eq!(opts.statistic, Statistic::Mean);
+        assert_eq!(opts.direction("throughput"), Direction::HigherIsBetter);
+        assert_eq!(
+            Config::require(&merged.results_dir, "results_dir").unwrap(),
+            PathBuf::from("results/")
+        );
+        // from the defaults
+        assert_eq!(opts.thresholds.global.stddev, 1.20);
+        assert_eq!(opts.test, ComparisonTest::Ratio);
+        assert_eq!(opts.alpha, 0.05);
+        assert_eq!(opts.direction("parse"), Direction::LowerIsBetter);
+        match Config::require(&merged.out_dir, "out_dir") {
+            Err(CalculateError::MissingSettingErr(name)) => assert_eq!(name, "out_dir"),
+            x => panic!("expected MissingSettingErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(toml::from_str::<Config>("sigma = 2.0").is_err());
+        assert!(toml::from_str::<Config>("statistic = \"mode\"").is_err());
+    }
+}
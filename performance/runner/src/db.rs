@@ -0,0 +1,139 @@
+use crate::calculate::Calculation;
+use crate::exceptions::CalculateError;
+use rusqlite::{params, Connection};
+use std::path::{Path, PathBuf};
+
+// Each migration moves the schema up one version, which sqlite tracks in
+// `user_version`. Migrations are only ever appended so that any database
+// can be brought up to date by running the ones it hasn't seen.
+const MIGRATIONS: &[&str] = &["CREATE TABLE calculations (
+        id INTEGER PRIMARY KEY,
+        commit_sha TEXT,
+        metric TEXT NOT NULL,
+        ts TEXT NOT NULL,
+        regression INTEGER NOT NULL,
+        calculation TEXT NOT NULL
+    );
+    CREATE INDEX calculations_metric_ts ON calculations (metric, ts);"];
+
+// A database of every calculation written with `--db`.
+pub struct Database {
+    path: PathBuf,
+    conn: Connection,
+}
+
+impl Database {
+    // Opens the database at `path`, creating it if it's missing, and brings
+    // its schema up to date.
+    pub fn open(path: &Path) -> Result<Self, CalculateError> {
+        let db = Database {
+            path: path.to_path_buf(),
+            conn: Connection::open(path)
+                .map_err(|e| CalculateError::SqliteErr(path.to_path_buf(), Some(e)))?,
+        };
+        db.migrate()?;
+        Ok(db)
+    }
+
+    fn err(&self, e: rusqlite::Error) -> CalculateError {
+        CalculateError::SqliteErr(self.path.clone(), Some(e))
+    }
+
+    fn migrate(&self) -> Result<(), CalculateError> {
+        let version: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|e| self.err(e))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            self.conn
+                .execute_batch(&format!(
+                    "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                    migration,
+                    i + 1
+                ))
+                .map_err(|e| self.err(e))?;
+        }
+        Ok(())
+    }
+
+    // Stores every calculation from a run, all or nothing.
+    pub fn insert(&mut self, calcs: &[Calculation]) -> Result<(), CalculateError> {
+        let rows = calcs
+            .iter()
+            .map(|c| serde_json::to_string(c).map(|json| (c, json)))
+            .collect::<Result<Vec<(&Calculation, String)>, serde_json::Error>>()
+            .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+
+        let path = self.path.clone();
+        let err = |e| CalculateError::SqliteErr(path.clone(), Some(e));
+        let tx = self.conn.transaction().map_err(err)?;
+        for (c, json) in rows {
+            tx.execute(
+                "INSERT INTO calculations (commit_sha, metric, ts, regression, calculation)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![c.commit, c.metric, c.ts.to_rfc3339(), c.regression, json],
+            )
+            .map_err(err)?;
+        }
+        tx.commit().map_err(err)
+    }
+
+    // The last `n` calculations stored for `metric`, newest first.
+    pub fn last(&self, metric: &str, n: usize) -> Result<Vec<Calculation>, CalculateError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT calculation FROM calculations WHERE metric = ?1
+                 ORDER BY ts DESC, id DESC LIMIT ?2",
+            )
+            .map_err(|e| self.err(e))?;
+        let rows = stmt
+            .query_map(params![metric, n as i64], |row| row.get::<_, String>(0))
+            .map_err(|e| self.err(e))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()
+            .map_err(|e| self.err(e))?;
+
+        rows.iter()
+            .map(|json| {
+                serde_json::from_str(json)
+                    .map_err(|e| CalculateError::BadJSONErr(self.path.clone(), Some(e)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measure::TmpDir;
+
+    use chrono::prelude::*;
+
+    #[test]
+    fn queries_the_latest_calculations() {
+        let calc = |metric: &str, day| Calculation {
+            regression: day == 30,
+            ts: Utc.ymd(2021, 7, day).and_hms(12, 0, 0),
+            commit: Some(format!("commit{}", day)),
+            ..Calculation::for_test(metric, 1.0, 1.0)
+        };
+
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let path = tmp.path().join("queries_the_latest_calculations.db");
+        let mut db = Database::open(&path).unwrap();
+        db.insert(&[calc("median_parse_a", 28), calc("median_parse_b", 28)])
+            .unwrap();
+        db.insert(&[calc("median_parse_a", 30), calc("median_parse_a", 29)])
+            .unwrap();
+        let last = db.last("median_parse_a", 2).unwrap();
+        // reopening doesn't rerun any migrations
+        drop(db);
+        let reopened = Database::open(&path).unwrap().last("median_parse_b", 10);
+
+        assert_eq!(
+            last,
+            vec![calc("median_parse_a", 30), calc("median_parse_a", 29)]
+        );
+        assert_eq!(reopened.unwrap(), vec![calc("median_parse_b", 28)]);
+    }
+}
@@ -1,4 +1,3 @@
-use crate::calculate::*;
 use std::io;
 #[cfg(test)]
 use std::path::Path;
@@ -9,9 +8,12 @@ use thiserror::Error;
 // New constructors should be added to wrap any new IO errors.
 // The desired output of these errors is tested below.
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum IOError {
     #[error("ReadErr: The file cannot be read.\nFilepath: {}\nOriginating Exception: {}", .0.to_string_lossy().into_owned(), .1.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
     ReadErr(PathBuf, Option<io::Error>),
+    #[error("WriteErr: The file cannot be written.\nFilepath: {}\nOriginating Exception: {}", .0.to_string_lossy().into_owned(), .1.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
+    WriteErr(PathBuf, Option<io::Error>),
     #[error("MissingFilenameErr: The path provided does not specify a file.\nFilepath: {}", .0.to_string_lossy().into_owned())]
     MissingFilenameErr(PathBuf),
     #[error("FilenameNotUnicodeErr: The filename is not expressible in unicode. Consider renaming the file.\nFilepath: {}", .0.to_string_lossy().into_owned())]
@@ -20,6 +22,10 @@ pub enum IOError {
     BadFileContentsErr(PathBuf, Option<io::Error>),
     #[error("CommandErr: System command failed to run.\nOriginating Exception: {}", .0.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
     CommandErr(Option<io::Error>),
+    #[error("CommandFailedErr: System command exited unsuccessfully.\nCommand: {}\nExit Code: {}", .0, .1.map_or("None".to_owned(), |c| c.to_string()))]
+    CommandFailedErr(String, Option<i32>),
+    #[error("ThreadPoolErr: Could not start the threads to run measurements on.\nOriginating Exception: {}", .0.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
+    ThreadPoolErr(Option<rayon::ThreadPoolBuildError>),
 }
 
 // Custom Error messages for the error states we could encounter
@@ -30,16 +36,69 @@ pub enum IOError {
 pub enum CalculateError {
     #[error("BadJSONErr: JSON in file cannot be deserialized as expected.\nFilepath: {}\nOriginating Exception: {}", .0.to_string_lossy().into_owned(), .1.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
     BadJSONErr(PathBuf, Option<serde_json::Error>),
+    #[error("BadTOMLErr: TOML in file cannot be deserialized as expected.\nFilepath: {}\nOriginating Exception: {}", .0.to_string_lossy().into_owned(), .1.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
+    BadTOMLErr(PathBuf, Option<toml::de::Error>),
+    #[error("BadCSVErr: Calculations cannot be serialized as csv.\nOriginating Exception: {}", .0.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
+    BadCSVErr(Option<csv::Error>),
+    #[error("JSONSerializeErr: Output cannot be serialized as json.\nOriginating Exception: {}", .0.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
+    JSONSerializeErr(Option<serde_json::Error>),
     #[error("{}", .0)]
-    CalculateIOError(IOError),
+    CalculateIOError(#[from] IOError),
+    #[cfg(feature = "sqlite")]
+    #[error("SqliteErr: The results database cannot be used.\nFilepath: {}\nOriginating Exception: {}", .0.to_string_lossy().into_owned(), .1.as_ref().map_or("None".to_owned(), |e| format!("{}", e)))]
+    SqliteErr(PathBuf, Option<rusqlite::Error>),
     #[error("NoResultsErr: The results directory has no json files in it.\nFilepath: {}", .0.to_string_lossy().into_owned())]
     NoResultsErr(PathBuf),
-    #[error("OddResultsCountErr: The results directory has an odd number of results in it. Expected an even number.\nFile Count: {}\nFilepath: {}", .0, .1.to_string_lossy().into_owned())]
-    OddResultsCountErr(usize, PathBuf),
-    #[error("BadGroupSizeErr: Expected two results per group, one for each branch-project pair.\nCount: {}\nGroup: {:?}", .0, .1.into_iter().map(|group| (&group.version[..], &group.run[..])).collect::<Vec<(&str, &str)>>())]
-    BadGroupSizeErr(usize, Vec<MeasurementGroup>),
+    #[error("NoFilteredResultsErr: Every results file in the directory was excluded by the project and metric filters.\nFilepath: {}", .0.to_string_lossy().into_owned())]
+    NoFilteredResultsErr(PathBuf),
+    #[error("NoBaselineResultsErr: The results directory has no results from the baseline branch in it.\nFilepath: {}", .0.to_string_lossy().into_owned())]
+    NoBaselineResultsErr(PathBuf),
+    #[error("BadGroupSizeErr: Expected two results per group, one for each branch-project pair.\nCount: {}\nGroup: {:?}", .0, .1.iter().map(|(version, run)| (&version[..], &run[..])).collect::<Vec<(&str, &str)>>())]
+    BadGroupSizeErr(usize, Vec<(String, String)>),
     #[error("BadBranchNameErr: Branch names must be 'baseline' and 'dev'.\nFound: {}, {}", .0, .1)]
     BadBranchNameErr(String, String),
+    #[error("BadMeasurementCountErr: Expected exactly one measurement in the results file.\nCount: {}\nFilepath: {}", .1, .0.to_string_lossy().into_owned())]
+    BadMeasurementCountErr(PathBuf, usize),
+    #[error("BaselineBranchNotFoundErr: No results were found for the requested baseline branch.\nBranch: {}\nRun: {}", .0, .1)]
+    BaselineBranchNotFoundErr(String, String),
+    #[error("DuplicateMetricErr: More than one result was found for the same branch, project, and metric.\nBranch: {}\nProject: {}\nMetric: {}", .0, .1, .2)]
+    DuplicateMetricErr(String, String, String),
+    #[error("MissingTimesErr: The comparison test needs every run's times, but this measurement only has summary statistics.\nBranch: {}\nProject: {}\nMetric: {}", .0, .1, .2)]
+    MissingTimesErr(String, String, String),
+    #[error("NoBranchResultsErr: The results directory has no results from the branch in it.\nBranch: {}\nFilepath: {}", .0, .1.to_string_lossy().into_owned())]
+    NoBranchResultsErr(String, PathBuf),
+    #[error("MixedBranchesErr: The results directory has results from more than one branch. Name the one to compare with --reference-branch or --candidate-branch.\nBranches: {}\nFilepath: {}", .1.join(", "), .0.to_string_lossy().into_owned())]
+    MixedBranchesErr(PathBuf, Vec<String>),
+    #[error("MissingSettingErr: This setting must be given as a flag or in the config file.\nSetting: {}", .0)]
+    MissingSettingErr(String),
+    #[error("HardwareMismatchErr: The baseline and dev results were measured on different hardware.\nBaseline: {}\nDev: {}", .0, .1)]
+    HardwareMismatchErr(String, String),
+    #[error("BadAlphaErr: Alpha must be between 0 and 1.\nFound: {}", .0)]
+    BadAlphaErr(f64),
+    #[error("BadEwmaAlphaErr: The EWMA alpha must be greater than 0 and at most 1.\nFound: {}", .0)]
+    BadEwmaAlphaErr(f64),
+    #[error("BadConfidenceErr: Confidence must be between 0 and 1.\nFound: {}", .0)]
+    BadConfidenceErr(f64),
+    #[error("BadBootstrapIterationsErr: The bootstrap must draw at least one resample.\nIterations: {}", .0)]
+    BadBootstrapIterationsErr(usize),
+    #[error("BadTrimErr: Trim must be at least 0 and less than 0.5.\nFound: {}", .0)]
+    BadTrimErr(f64),
+    #[error("BadRunsErr: Each command must be timed at least once.\nRuns: {}", .0)]
+    BadRunsErr(usize),
+    #[error("BadThresholdErr: Thresholds must be positive numbers.\nThreshold: {}\nFound: {}", .0, .1)]
+    BadThresholdErr(String, f64),
+    #[error("NonFiniteErr: Measurements must be finite numbers.\nField: {}\nFound: {}\nFilepath: {}", .1, .2, .0.to_string_lossy().into_owned())]
+    NonFiniteErr(PathBuf, String, f64),
+    #[error("BadMetricNameErr: Metric names must be non-empty and can't contain underscores.\nMetric: {}\nFilepath: {}", .1, .0.to_string_lossy().into_owned())]
+    BadMetricNameErr(PathBuf, String),
+    #[error("BadTemplateErr: A command template has a '{{{{' that is never closed.\nTemplate: {}\nFilepath: {}", .1, .0.to_string_lossy().into_owned())]
+    BadTemplateErr(PathBuf, String),
+    #[error("UnknownTemplateVarErr: A command template uses a variable that isn't defined.\nVariable: {}\nFilepath: {}", .1, .0.to_string_lossy().into_owned())]
+    UnknownTemplateVarErr(PathBuf, String),
+    #[error("EmptySamplesErr: There were no samples to read.\nFilepath: {}", .0.to_string_lossy().into_owned())]
+    EmptySamplesErr(PathBuf),
+    #[error("SamplesPairErr: Samples that aren't read from a results directory must be named with exactly one --project and one --metric.\nProjects: {}\nMetrics: {}", .0, .1)]
+    SamplesPairErr(usize, usize),
 }
 
 // Tests for exceptions
@@ -55,6 +114,12 @@ mod tests {
                 IOError::ReadErr(Path::new("dummy/path/file.json").to_path_buf(), None),
                 r#"ReadErr: The file cannot be read.
 Filepath: dummy/path/file.json
+Originating Exception: None"#,
+            ),
+            (
+                IOError::WriteErr(Path::new("dummy/path/file.json").to_path_buf(), None),
+                r#"WriteErr: The file cannot be written.
+Filepath: dummy/path/file.json
 Originating Exception: None"#,
             ),
             (
@@ -79,6 +144,17 @@ Originating Exception: None"#,
             (
                 IOError::CommandErr(None),
                 r#"CommandErr: System command failed to run.
+Originating Exception: None"#,
+            ),
+            (
+                IOError::CommandFailedErr("dbt parse".to_owned(), Some(2)),
+                r#"CommandFailedErr: System command exited unsuccessfully.
+Command: dbt parse
+Exit Code: 2"#,
+            ),
+            (
+                IOError::ThreadPoolErr(None),
+                r#"ThreadPoolErr: Could not start the threads to run measurements on.
 Originating Exception: None"#,
             ),
         ];
@@ -102,6 +178,30 @@ Originating Exception: None"#,
                 CalculateError::BadJSONErr(Path::new("dummy/path/file.json").to_path_buf(), None),
                 r#"BadJSONErr: JSON in file cannot be deserialized as expected.
 Filepath: dummy/path/file.json
+Originating Exception: None"#,
+            ),
+            (
+                CalculateError::BadTOMLErr(
+                    Path::new("dummy/path/thresholds.toml").to_path_buf(),
+                    None,
+                ),
+                r#"BadTOMLErr: TOML in file cannot be deserialized as expected.
+Filepath: dummy/path/thresholds.toml
+Originating Exception: None"#,
+            ),
+            (
+                CalculateError::BadCSVErr(None),
+                r#"BadCSVErr: Calculations cannot be serialized as csv.
+Originating Exception: None"#,
+            ),
+            (
+                CalculateError::from(IOError::CommandErr(None)),
+                r#"CommandErr: System command failed to run.
+Originating Exception: None"#,
+            ),
+            (
+                CalculateError::JSONSerializeErr(None),
+                r#"JSONSerializeErr: Output cannot be serialized as json.
 Originating Exception: None"#,
             ),
             (
@@ -110,32 +210,23 @@ Originating Exception: None"#,
 Filepath: dummy/path/no_file/"#,
             ),
             (
-                CalculateError::OddResultsCountErr(
-                    3,
+                CalculateError::NoFilteredResultsErr(
                     Path::new("dummy/path/no_file/").to_path_buf(),
                 ),
-                r#"OddResultsCountErr: The results directory has an odd number of results in it. Expected an even number.
-File Count: 3
+                r#"NoFilteredResultsErr: Every results file in the directory was excluded by the project and metric filters.
+Filepath: dummy/path/no_file/"#,
+            ),
+            (
+                CalculateError::NoBaselineResultsErr(
+                    Path::new("dummy/path/no_file/").to_path_buf(),
+                ),
+                r#"NoBaselineResultsErr: The results directory has no results from the baseline branch in it.
 Filepath: dummy/path/no_file/"#,
             ),
             (
                 CalculateError::BadGroupSizeErr(
                     1,
-                    vec![MeasurementGroup {
-                        version: "dev".to_owned(),
-                        run: "some command".to_owned(),
-                        measurement: Measurement {
-                            command: "some command".to_owned(),
-                            mean: 1.0,
-                            stddev: 1.0,
-                            median: 1.0,
-                            user: 1.0,
-                            system: 1.0,
-                            min: 1.0,
-                            max: 1.0,
-                            times: vec![1.0, 1.1, 0.9, 1.0, 1.1, 0.9, 1.1],
-                        },
-                    }],
+                    vec![("dev".to_owned(), "some command".to_owned())],
                 ),
                 r#"BadGroupSizeErr: Expected two results per group, one for each branch-project pair.
 Count: 1
@@ -146,10 +237,181 @@ Group: [("dev", "some command")]"#,
                 r#"BadBranchNameErr: Branch names must be 'baseline' and 'dev'.
 Found: boop, noop"#,
             ),
+            (
+                CalculateError::BadMeasurementCountErr(
+                    Path::new("dummy/path/file.json").to_path_buf(),
+                    0,
+                ),
+                r#"BadMeasurementCountErr: Expected exactly one measurement in the results file.
+Count: 0
+Filepath: dummy/path/file.json"#,
+            ),
+            (
+                CalculateError::BaselineBranchNotFoundErr(
+                    "0.19.latest".to_owned(),
+                    "parse_project.json".to_owned(),
+                ),
+                r#"BaselineBranchNotFoundErr: No results were found for the requested baseline branch.
+Branch: 0.19.latest
+Run: parse_project.json"#,
+            ),
+            (
+                CalculateError::DuplicateMetricErr(
+                    "dev".to_owned(),
+                    "project".to_owned(),
+                    "parse".to_owned(),
+                ),
+                r#"DuplicateMetricErr: More than one result was found for the same branch, project, and metric.
+Branch: dev
+Project: project
+Metric: parse"#,
+            ),
+            (
+                CalculateError::MissingTimesErr(
+                    "baseline".to_owned(),
+                    "project".to_owned(),
+                    "parse".to_owned(),
+                ),
+                r#"MissingTimesErr: The comparison test needs every run's times, but this measurement only has summary statistics.
+Branch: baseline
+Project: project
+Metric: parse"#,
+            ),
+            (
+                CalculateError::NoBranchResultsErr(
+                    "0.19.latest".to_owned(),
+                    Path::new("dummy/path/").to_path_buf(),
+                ),
+                r#"NoBranchResultsErr: The results directory has no results from the branch in it.
+Branch: 0.19.latest
+Filepath: dummy/path/"#,
+            ),
+            (
+                CalculateError::MixedBranchesErr(
+                    Path::new("dummy/path/").to_path_buf(),
+                    vec!["baseline".to_owned(), "dev".to_owned()],
+                ),
+                r#"MixedBranchesErr: The results directory has results from more than one branch. Name the one to compare with --reference-branch or --candidate-branch.
+Branches: baseline, dev
+Filepath: dummy/path/"#,
+            ),
+            (
+                CalculateError::MissingSettingErr("results_dir".to_owned()),
+                r#"MissingSettingErr: This setting must be given as a flag or in the config file.
+Setting: results_dir"#,
+            ),
+            (
+                CalculateError::HardwareMismatchErr(
+                    "intel xeon, 2 cores, linux-x86_64".to_owned(),
+                    "amd epyc, 4 cores, linux-x86_64".to_owned(),
+                ),
+                r#"HardwareMismatchErr: The baseline and dev results were measured on different hardware.
+Baseline: intel xeon, 2 cores, linux-x86_64
+Dev: amd epyc, 4 cores, linux-x86_64"#,
+            ),
+            (
+                CalculateError::BadAlphaErr(1.5),
+                r#"BadAlphaErr: Alpha must be between 0 and 1.
+Found: 1.5"#,
+            ),
+            (
+                CalculateError::BadEwmaAlphaErr(0.0),
+                r#"BadEwmaAlphaErr: The EWMA alpha must be greater than 0 and at most 1.
+Found: 0"#,
+            ),
+            (
+                CalculateError::BadConfidenceErr(95.0),
+                r#"BadConfidenceErr: Confidence must be between 0 and 1.
+Found: 95"#,
+            ),
+            (
+                CalculateError::BadBootstrapIterationsErr(0),
+                r#"BadBootstrapIterationsErr: The bootstrap must draw at least one resample.
+Iterations: 0"#,
+            ),
+            (
+                CalculateError::EmptySamplesErr(Path::new("<stdin>").to_path_buf()),
+                r#"EmptySamplesErr: There were no samples to read.
+Filepath: <stdin>"#,
+            ),
+            (
+                CalculateError::SamplesPairErr(2, 0),
+                r#"SamplesPairErr: Samples that aren't read from a results directory must be named with exactly one --project and one --metric.
+Projects: 2
+Metrics: 0"#,
+            ),
+            (
+                CalculateError::BadTrimErr(0.5),
+                r#"BadTrimErr: Trim must be at least 0 and less than 0.5.
+Found: 0.5"#,
+            ),
+            (
+                CalculateError::BadRunsErr(0),
+                r#"BadRunsErr: Each command must be timed at least once.
+Runs: 0"#,
+            ),
+            (
+                CalculateError::BadThresholdErr("median".to_owned(), -1.0),
+                r#"BadThresholdErr: Thresholds must be positive numbers.
+Threshold: median
+Found: -1"#,
+            ),
+            (
+                CalculateError::NonFiniteErr(
+                    Path::new("dummy/path/file.json").to_path_buf(),
+                    "mean".to_owned(),
+                    f64::NAN,
+                ),
+                r#"NonFiniteErr: Measurements must be finite numbers.
+Field: mean
+Found: NaN
+Filepath: dummy/path/file.json"#,
+            ),
+            (
+                CalculateError::BadMetricNameErr(
+                    Path::new("dummy/path/benchmark.toml").to_path_buf(),
+                    "full_parse".to_owned(),
+                ),
+                r#"BadMetricNameErr: Metric names must be non-empty and can't contain underscores.
+Metric: full_parse
+Filepath: dummy/path/benchmark.toml"#,
+            ),
+            (
+                CalculateError::BadTemplateErr(
+                    Path::new("dummy/path/benchmark.toml").to_path_buf(),
+                    "dbt parse {{ target".to_owned(),
+                ),
+                r#"BadTemplateErr: A command template has a '{{' that is never closed.
+Template: dbt parse {{ target
+Filepath: dummy/path/benchmark.toml"#,
+            ),
+            (
+                CalculateError::UnknownTemplateVarErr(
+                    Path::new("dummy/path/benchmark.toml").to_path_buf(),
+                    "target".to_owned(),
+                ),
+                r#"UnknownTemplateVarErr: A command template uses a variable that isn't defined.
+Variable: target
+Filepath: dummy/path/benchmark.toml"#,
+            ),
         ];
 
         for (err, msg) in pairs {
             assert_eq!(format!("{}", err), msg)
         }
     }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_error_messages() {
+        assert_eq!(
+            format!(
+                "{}",
+                CalculateError::SqliteErr(Path::new("dummy/path/runs.db").to_path_buf(), None)
+            ),
+            r#"SqliteErr: The results database cannot be used.
+Filepath: dummy/path/runs.db
+Originating Exception: None"#
+        );
+    }
 }
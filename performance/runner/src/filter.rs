@@ -0,0 +1,77 @@
+use structopt::StructOpt;
+
+// Restricts which projects and metrics are measured and compared. An empty
+// list of projects or metrics means every one is included.
+#[derive(Clone, Debug, Default, PartialEq, StructOpt)]
+pub struct Filter {
+    /// only include this project. can be repeated
+    #[structopt(long = "project")]
+    pub projects: Vec<String>,
+    /// leave out this project. can be repeated
+    #[structopt(long = "exclude-project")]
+    pub exclude_projects: Vec<String>,
+    /// only include this metric (e.g. parse). can be repeated
+    #[structopt(long = "metric")]
+    pub metrics: Vec<String>,
+}
+
+impl Filter {
+    pub fn includes_project(&self, project: &str) -> bool {
+        (self.projects.is_empty() || self.projects.iter().any(|p| p == project))
+            && !self.exclude_projects.iter().any(|p| p == project)
+    }
+
+    pub fn includes_metric(&self, metric: &str) -> bool {
+        self.metrics.is_empty() || self.metrics.iter().any(|m| m == metric)
+    }
+
+    pub fn includes(&self, project: &str, metric: &str) -> bool {
+        self.includes_project(project) && self.includes_metric(metric)
+    }
+
+    // Returns every project named in the filter that isn't in `known`, so
+    // a typo can be reported rather than silently matching nothing.
+    pub fn unknown_projects(&self, known: &[String]) -> Vec<&str> {
+        self.projects
+            .iter()
+            .chain(self.exclude_projects.iter())
+            .filter(|p| !known.contains(p))
+            .map(|p| &p[..])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_projects() {
+        let everything = Filter::default();
+        assert!(everything.includes_project("a"));
+
+        let filter = Filter {
+            projects: vec!["a".to_owned(), "b".to_owned()],
+            exclude_projects: vec!["b".to_owned(), "z".to_owned()],
+            ..Filter::default()
+        };
+        assert!(filter.includes_project("a"));
+        assert!(!filter.includes_project("b"));
+        assert!(!filter.includes_project("c"));
+
+        let known = vec!["a".to_owned(), "b".to_owned(), "c".to_owned()];
+        assert_eq!(filter.unknown_projects(&known), vec!["z"]);
+    }
+
+    #[test]
+    fn composes_project_and_metric_filters() {
+        let filter = Filter {
+            projects: vec!["jaffle".to_owned()],
+            metrics: vec!["parse".to_owned()],
+            ..Filter::default()
+        };
+        assert!(filter.includes("jaffle", "parse"));
+        assert!(!filter.includes("jaffle", "run"));
+        assert!(!filter.includes("other", "parse"));
+    }
+}
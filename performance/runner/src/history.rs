@@ -0,0 +1,506 @@
+use crate::calculate::{Calculation, Direction, Measurement, MetricKey, Statistic};
+use crate::exceptions::{CalculateError, IOError};
+use crate::stats;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+// Appends every calculation to a json-lines history file, one calculation
+// per line, creating the file if it's missing. Each calculation carries its
+// own timestamp and commit so lines from many runs can be told apart.
+//
+// The whole run is written with a single append so concurrent runs sharing
+// a history file can't interleave their lines.
+pub fn append(path: &Path, calcs: &[Calculation]) -> Result<(), CalculateError> {
+    let lines = calcs
+        .iter()
+        .map(|c| serde_json::to_string(c).map(|line| line + "\n"))
+        .collect::<Result<String, serde_json::Error>>()
+        .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(lines.as_bytes()))
+        .map_err(|e| IOError::WriteErr(path.to_path_buf(), Some(e)))?;
+
+    Ok(())
+}
+
+// Every calculation in the history file, oldest first. A history file that
+// doesn't exist yet has no calculations in it.
+pub fn read(path: &Path) -> Result<Vec<Calculation>, CalculateError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(IOError::BadFileContentsErr(path.to_path_buf(), Some(e)).into()),
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| CalculateError::BadJSONErr(path.to_path_buf(), Some(e)))
+        })
+        .collect()
+}
+
+// A project-metric pair whose verdict flipped between regressed and not
+// regressed in more than half of its recent consecutive runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quarantine {
+    pub key: MetricKey,
+    pub flips: usize,
+    pub runs: usize,
+}
+
+// Finds the pairs too flaky to gate on, from the last `window` runs of each
+// in `history`. A run of a pair regressed if any of its calculations did.
+// A sustained shift flips the verdict once, so it's never quarantined, and
+// fewer than three runs can't show oscillation.
+pub fn quarantined(history: &[Calculation], window: usize) -> Vec<Quarantine> {
+    // the calculations of one run of a pair share a timestamp
+    let mut runs: BTreeMap<MetricKey, BTreeMap<DateTime<Utc>, bool>> = BTreeMap::new();
+    for c in history {
+        *runs
+            .entry(MetricKey::from_calculation(&c.metric))
+            .or_default()
+            .entry(c.ts)
+            .or_default() |= c.regression;
+    }
+
+    runs.into_iter()
+        .filter_map(|(key, verdicts)| {
+            let verdicts: Vec<bool> = verdicts.into_values().collect();
+            let recent = &verdicts[verdicts.len().saturating_sub(window)..];
+            let flips = recent.windows(2).filter(|w| w[0] != w[1]).count();
+            if recent.len() >= 3 && flips * 2 > recent.len() - 1 {
+                Some(Quarantine {
+                    key,
+                    flips,
+                    runs: recent.len(),
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// A project-metric pair whose compared statistic has been getting worse run
+// after run, by a trend too steady to be noise. No single run moves enough to
+// regress, so these are reported apart from the regressions.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Drift {
+    pub project: String,
+    pub metric: String,
+    pub runs: usize,
+    // change in the statistic per run, in its own units
+    pub slope: f64,
+    // the slope as a percentage of the mean over those runs
+    pub pct_per_run: Option<f64>,
+    pub p_value: f64,
+}
+
+// Finds the pairs drifting worse over their last `window` runs in
+// `history`, by fitting a line to the `statistic` dev was compared by in
+// each run and testing its slope at `alpha`. A higher-is-better metric
+// drifts worse by going down. Fewer than three runs can't show a trend.
+pub fn drifting(history: &[Calculation], statistic: &str, window: usize, alpha: f64) -> Vec<Drift> {
+    // the calculations of one run of a pair share a timestamp
+    let mut series: BTreeMap<MetricKey, BTreeMap<DateTime<Utc>, (f64, Direction)>> =
+        BTreeMap::new();
+    for c in history {
+        if c.metric.split_once('_').map(|(stat, _)| stat) == Some(statistic) {
+            series
+                .entry(MetricKey::from_calculation(&c.metric))
+                .or_default()
+                .insert(c.ts, (c.data.dev, c.direction));
+        }
+    }
+
+    series
+        .into_iter()
+        .filter_map(|(key, runs)| {
+            let runs: Vec<(f64, Direction)> = runs.into_values().collect();
+            let recent = &runs[runs.len().saturating_sub(window)..];
+            let values: Vec<f64> = recent.iter().map(|(v, _)| *v).collect();
+            let trend = stats::linear_trend(&values)?;
+            // the latest run says which way the metric is meant to go
+            let worse = match recent.last()?.1 {
+                Direction::LowerIsBetter => trend.slope > 0.0,
+                Direction::HigherIsBetter => trend.slope < 0.0,
+            };
+            if !worse || trend.p_value >= alpha {
+                return None;
+            }
+            Some(Drift {
+                project: key.project,
+                metric: key.metric,
+                runs: values.len(),
+                slope: trend.slope,
+                pct_per_run: stats::mean(&values)
+                    .and_then(|m| stats::safe_ratio(trend.slope, m))
+                    .map(|r| r * 100.0),
+                p_value: trend.p_value,
+            })
+        })
+        .collect()
+}
+
+// A run's compared statistic and, when it was recorded, its stddev.
+type RunSample = (f64, Option<f64>);
+
+// The `statistic` dev was compared by and the stddev of each of the last
+// `window` runs of every project-metric pair in `history`, oldest first.
+// Keyed by the run portion of a results filename, `{metric}_{project}.json`.
+fn recent_runs(
+    history: &[Calculation],
+    statistic: &str,
+    window: usize,
+) -> Vec<(String, Vec<RunSample>)> {
+    // the calculations of one run of a pair share a timestamp
+    let mut samples: BTreeMap<&str, BTreeMap<DateTime<Utc>, f64>> = BTreeMap::new();
+    let mut stddevs: BTreeMap<&str, BTreeMap<DateTime<Utc>, f64>> = BTreeMap::new();
+    for c in history {
+        let (stat, run) = match c.metric.split_once('_') {
+            Some(split) => split,
+            None => continue,
+        };
+        if stat == statistic {
+            samples.entry(run).or_default().insert(c.ts, c.data.dev);
+        } else if stat == "stddev" {
+            stddevs.entry(run).or_default().insert(c.ts, c.data.dev);
+        }
+    }
+
+    samples
+        .into_iter()
+        .map(|(run, by_ts)| {
+            let runs: Vec<RunSample> = by_ts
+                .into_iter()
+                .map(|(ts, v)| (v, stddevs.get(run).and_then(|s| s.get(&ts)).copied()))
+                .collect();
+            let recent = runs[runs.len().saturating_sub(window)..].to_vec();
+            (run.to_owned(), recent)
+        })
+        .collect()
+}
+
+// A baseline made of the samples of some recent runs. Its summary
+// statistics are taken over the samples, and its stddev is the mean of the
+// runs' own stddevs, so the stddev comparison still measures noise within a
+// run. `None` with fewer than two samples.
+fn baseline_from_runs(runs: &[RunSample]) -> Option<Measurement> {
+    let times: Vec<f64> = runs.iter().map(|(v, _)| *v).collect();
+    let run_stddevs: Vec<f64> = runs.iter().filter_map(|(_, s)| *s).collect();
+    let variance = stats::variance(&times)?;
+    let baseline = Measurement {
+        command: format!("last {} runs in the history", times.len()),
+        mean: stats::mean(&times)?,
+        stddev: stats::mean(&run_stddevs).unwrap_or_else(|| variance.sqrt()),
+        median: stats::median(&times)?,
+        // cpu times aren't recorded in the history
+        user: 0.0,
+        system: 0.0,
+        min: times.iter().copied().fold(f64::INFINITY, f64::min),
+        max: times.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        times,
+        p95: None,
+        p99: None,
+        exit_codes: vec![],
+    };
+    Some(baseline.with_percentiles())
+}
+
+// A baseline for each project-metric pair built from its last `window`
+// runs in `history`, for comparing against recent runs rather than a
+// released baseline. Keyed by the run portion of a results filename,
+// `{metric}_{project}.json`. A run's sample is the `statistic` dev was
+// compared by, so the baseline's times are those samples and its summary
+// statistics are taken over them. Its stddev is the mean of the runs' own
+// stddevs, so the stddev comparison still measures noise within a run.
+// Pairs with fewer than two runs recorded have nothing to compare against
+// yet, and get no baseline.
+pub fn rolling_baselines(
+    history: &[Calculation],
+    statistic: &str,
+    window: usize,
+) -> Vec<(String, Measurement)> {
+    recent_runs(history, statistic, window)
+        .into_iter()
+        .filter_map(|(run, runs)| Some((run, baseline_from_runs(&runs)?)))
+        .collect()
+}
+
+// Like `rolling_baselines`, but the compared statistic and the stddev are
+// exponentially weighted moving averages over the window, so recent runs
+// count for more and one anomalous run fades out instead of weighing as
+// much as the rest until it leaves the window. The newest run weighs
+// `alpha`, and each older one `1 - alpha` times the one after it.
+pub fn ewma_baselines(
+    history: &[Calculation],
+    statistic: Statistic,
+    window: usize,
+    alpha: f64,
+) -> Result<Vec<(String, Measurement)>, CalculateError> {
+    if !(alpha > 0.0 && alpha <= 1.0) {
+        return Err(CalculateError::BadEwmaAlphaErr(alpha));
+    }
+    let ewma = |xs: &mut dyn Iterator<Item = f64>| {
+        xs.reduce(|smoothed, x| alpha * x + (1.0 - alpha) * smoothed)
+    };
+
+    Ok(recent_runs(history, statistic.name(), window)
+        .into_iter()
+        .filter_map(|(run, runs)| {
+            let mut baseline = baseline_from_runs(&runs)?;
+            let smoothed = ewma(&mut runs.iter().map(|(v, _)| *v))?;
+            match statistic {
+                Statistic::Mean => baseline.mean = smoothed,
+                Statistic::Median => baseline.median = smoothed,
+                Statistic::Min => baseline.min = smoothed,
+                Statistic::P95 => baseline.p95 = Some(smoothed),
+                Statistic::P99 => baseline.p99 = Some(smoothed),
+            }
+            if let Some(stddev) = ewma(&mut runs.iter().filter_map(|(_, s)| *s)) {
+                baseline.stddev = stddev;
+            }
+            baseline.command = format!(
+                "ewma of the last {} runs in the history at alpha {}",
+                runs.len(),
+                alpha
+            );
+            Some((run, baseline))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calculate::Data;
+    use crate::measure::TmpDir;
+    use chrono::prelude::*;
+
+    #[test]
+    fn round_trips_appended_runs() {
+        let calc = |metric: &str, day| {
+            let calc = Calculation::for_test(metric, 1.0, 1.0);
+            Calculation {
+                ts: Utc.ymd(2021, 7, day).and_hms(12, 0, 0),
+                commit: Some("abc123".to_owned()),
+                data: Data {
+                    ci_low: Some(0.9),
+                    ci_high: Some(1.1),
+                    ..calc.data
+                },
+                ..calc
+            }
+        };
+        let first = vec![calc("median_parse_a", 28), calc("stddev_parse_a", 28)];
+        let second = vec![calc("median_parse_a", 29)];
+
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let path = tmp.path().join("round_trips_appended_runs.jsonl");
+        append(&path, &first).unwrap();
+        append(&path, &second).unwrap();
+        let history = read(&path).unwrap();
+
+        assert_eq!(history, [first, second].concat());
+        assert_eq!(read(&tmp.path().join("missing.jsonl")).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn round_trips_calculations_from_a_zero_baseline() {
+        let calcs = vec![
+            Calculation::for_test("median_parse_a", 0.0, 0.0),
+            Calculation::for_test("stddev_parse_a", 0.0, 0.1),
+        ];
+        assert_eq!(calcs[0].data.difference, None);
+
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let path = tmp
+            .path()
+            .join("round_trips_calculations_from_a_zero_baseline.jsonl");
+        append(&path, &calcs).unwrap();
+        let history = read(&path).unwrap();
+
+        assert_eq!(history, calcs);
+    }
+
+    #[test]
+    fn reads_lines_from_older_versions() {
+        // written before calculations recorded failed runs or the absolute
+        // floor they were held to
+        let line = r#"{"metric":"median_parse_a","regression":false,"improvement":false,"ts":"2021-07-29T12:00:00Z","rule":"ratio","noisy":false,"commit":null,"data":{"threshold":1.05,"difference":1.0,"pct_change":0.0,"baseline":1.0,"dev":1.0}}"#;
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let path = tmp.path().join("reads_lines_from_older_versions.jsonl");
+        std::fs::write(&path, format!("{}\n", line)).unwrap();
+        let history = read(&path).unwrap();
+
+        assert_eq!(
+            history,
+            vec![Calculation::for_test("median_parse_a", 1.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn quarantines_oscillating_metrics() {
+        let run = |metric: &str, day, regression| Calculation {
+            regression,
+            ts: Utc.ymd(2021, 7, day).and_hms(12, 0, 0),
+            ..Calculation::for_test(metric, 1.0, 1.0)
+        };
+        let history = |metric: &str, verdicts: &[bool]| -> Vec<Calculation> {
+            verdicts
+                .iter()
+                .enumerate()
+                .flat_map(|(day, r)| {
+                    vec![
+                        run(&format!("median_{}", metric), day as u32 + 1, *r),
+                        run(&format!("stddev_{}", metric), day as u32 + 1, false),
+                    ]
+                })
+                .collect()
+        };
+        let (t, f) = (true, false);
+        let history = [
+            history("parse_flaky.json", &[f, t, f, t, f, t]),
+            history("parse_shifted.json", &[f, f, f, t, t, t]),
+            history("parse_blip.json", &[f, f, t, f, f, f]),
+            // only flaky before the window
+            history("parse_settled.json", &[t, f, t, f, f, f, f]),
+        ]
+        .concat();
+
+        assert_eq!(
+            quarantined(&history, 5),
+            vec![Quarantine {
+                key: MetricKey::new("flaky", "parse"),
+                flips: 4,
+                runs: 5,
+            }]
+        );
+        assert_eq!(quarantined(&history, 2), vec![]);
+    }
+
+    #[test]
+    fn finds_metrics_drifting_worse() {
+        let run = |metric: &str, day, dev| Calculation {
+            ts: Utc.ymd(2021, 7, day).and_hms(12, 0, 0),
+            ..Calculation::for_test(metric, 1.0, dev)
+        };
+        let mut history = vec![];
+        for day in 1..=10 {
+            let step = f64::from(day);
+            // creeping up 1% a run, with a little noise
+            let noise = if day % 2 == 0 { 0.002 } else { -0.002 };
+            history.push(run("median_parse_a", day, 1.0 + 0.01 * step + noise));
+            history.push(run("stddev_parse_a", day, 0.5 + 0.1 * step));
+            // as noisy, but not going anywhere
+            history.push(run("median_parse_b", day, 1.0 + noise * 5.0));
+            // improving steadily
+            history.push(run("median_parse_c", day, 2.0 - 0.01 * step));
+            // a throughput falling steadily
+            history.push(Calculation {
+                direction: Direction::HigherIsBetter,
+                ..run("median_throughput_d", day, 100.0 - step)
+            });
+        }
+
+        let drifts = drifting(&history, "median", 5, 0.05);
+        let drifted: Vec<(&str, &str, usize)> = drifts
+            .iter()
+            .map(|d| (&d.project[..], &d.metric[..], d.runs))
+            .collect();
+        assert_eq!(drifted, vec![("a", "parse", 5), ("d", "throughput", 5)]);
+        assert!((drifts[0].slope - 0.01).abs() < 0.005);
+        assert!(drifts[0].pct_per_run.unwrap() > 0.5);
+
+        // two runs are no trend at all
+        assert_eq!(drifting(&history, "median", 2, 0.05), vec![]);
+    }
+
+    #[test]
+    fn builds_rolling_baselines_from_recent_runs() {
+        let run = |metric: &str, day, dev| Calculation {
+            ts: Utc.ymd(2021, 7, day).and_hms(12, 0, 0),
+            ..Calculation::for_test(metric, 1.0, dev)
+        };
+        let history = vec![
+            // too old for a window of three
+            run("median_parse_a.json", 1, 9.0),
+            run("stddev_parse_a.json", 1, 9.0),
+            run("median_parse_a.json", 2, 1.0),
+            run("stddev_parse_a.json", 2, 0.1),
+            run("median_parse_a.json", 3, 1.2),
+            run("stddev_parse_a.json", 3, 0.3),
+            run("median_parse_a.json", 4, 1.1),
+            run("stddev_parse_a.json", 4, 0.2),
+            // compared by a different statistic
+            run("mean_parse_a.json", 4, 5.0),
+            // a single run is too few to build a baseline from
+            run("median_parse_b.json", 4, 1.0),
+            run("stddev_parse_b.json", 4, 0.1),
+        ];
+
+        let baselines = rolling_baselines(&history, "median", 3);
+        assert_eq!(baselines.len(), 1);
+        let (run, baseline) = &baselines[0];
+        assert_eq!(run, "parse_a.json");
+        assert_eq!(baseline.times, vec![1.0, 1.2, 1.1]);
+        assert!((baseline.mean - 1.1).abs() < 1e-9);
+        assert!((baseline.median - 1.1).abs() < 1e-9);
+        assert!((baseline.stddev - 0.2).abs() < 1e-9);
+        assert_eq!((baseline.min, baseline.max), (1.0, 1.2));
+
+        // a short history uses every run it has
+        assert_eq!(
+            rolling_baselines(&history, "median", 20)[0].1.times.len(),
+            4
+        );
+        assert!(rolling_baselines(&[], "median", 20).is_empty());
+    }
+
+    #[test]
+    fn weights_recent_runs_for_ewma_baselines() {
+        let run = |metric: &str, day, dev| Calculation {
+            ts: Utc.ymd(2021, 7, day).and_hms(12, 0, 0),
+            ..Calculation::for_test(metric, 1.0, dev)
+        };
+        let history = vec![
+            // one anomalous run, now the oldest in the window
+            run("median_parse_a.json", 1, 2.0),
+            run("stddev_parse_a.json", 1, 0.4),
+            run("median_parse_a.json", 2, 1.0),
+            run("stddev_parse_a.json", 2, 0.1),
+            run("median_parse_a.json", 3, 1.0),
+            run("stddev_parse_a.json", 3, 0.1),
+        ];
+
+        let baselines = ewma_baselines(&history, Statistic::Median, 3, 0.5).unwrap();
+        let (run, baseline) = &baselines[0];
+        assert_eq!(run, "parse_a.json");
+        // 2.0, then halfway to 1.0 twice
+        assert!((baseline.median - 1.25).abs() < 1e-9);
+        assert!((baseline.stddev - 0.175).abs() < 1e-9);
+        // the rolling baseline weighs it as much as the others
+        let rolling = rolling_baselines(&history, "median", 3);
+        assert!((rolling[0].1.mean - 4.0 / 3.0).abs() < 1e-9);
+        // an alpha of 1 only keeps the newest run
+        let newest = ewma_baselines(&history, Statistic::Median, 3, 1.0).unwrap();
+        assert!((newest[0].1.median - 1.0).abs() < 1e-9);
+
+        for alpha in [0.0, 1.5, f64::NAN].iter() {
+            match ewma_baselines(&history, Statistic::Median, 3, *alpha) {
+                Err(CalculateError::BadEwmaAlphaErr(_)) => (),
+                x => panic!("expected BadEwmaAlphaErr, got {:?}", x),
+            }
+        }
+    }
+}
@@ -1,134 +1,1106 @@
 extern crate structopt;
 
+mod accept;
 mod calculate;
+mod config;
+#[cfg(feature = "sqlite")]
+mod db;
 mod exceptions;
+mod filter;
+mod history;
+mod manifest;
 mod measure;
+mod metadata;
+mod notify;
+mod output;
+mod stats;
+mod summary;
 
-use crate::calculate::Calculation;
-use crate::exceptions::CalculateError;
+use crate::calculate::{
+    BaselineMode, CalculateOptions, Calculation, ComparisonTest, Correction, Measurements,
+    Statistic,
+};
+use crate::config::Config;
+use crate::exceptions::{CalculateError, IOError};
+use crate::filter::Filter;
+use crate::output::{ColorChoice, OutputFormat};
 use chrono::offset::Utc;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use indicatif::{ProgressBar, ProgressStyle};
 use std::fs::metadata;
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use structopt::StructOpt;
+use tracing_subscriber::EnvFilter;
 
-// This type defines the commandline interface and is generated
-// by `derive(StructOpt)`
+// These types define the commandline interface and are generated
+// by `derive(StructOpt)`. It's parsed exactly once, so the size
+// difference between subcommands doesn't matter.
 #[derive(Clone, Debug, StructOpt)]
 #[structopt(name = "performance", about = "performance regression testing runner")]
+struct Args {
+    /// log what the runner is doing to stderr: -v for progress, -vv for every file read,
+    /// command run, and pair matched. without it, RUST_LOG sets the level
+    #[structopt(short, long, parse(from_occurrences), global = true)]
+    verbose: u8,
+    #[structopt(subcommand)]
+    cmd: Opt,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, StructOpt)]
 enum Opt {
     #[structopt(name = "measure")]
     Measure {
+        /// config file to read settings from. defaults to runner.toml if there is one
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
         #[structopt(parse(from_os_str))]
         #[structopt(short)]
-        projects_dir: PathBuf,
+        projects_dir: Option<PathBuf>,
         #[structopt(short)]
         branch_name: String,
+        #[structopt(flatten)]
+        filter: Filter,
+        /// number of project-metric pairs to measure at once. 0 uses one per cpu.
+        /// each hyperfine run is already multi-process, so keep this low. the measured
+        /// commands' output is only shown when this is 1
+        #[structopt(long, default_value = "1")]
+        jobs: usize,
+        /// tool to time commands with: hyperfine
+        #[structopt(long, default_value = "hyperfine")]
+        backend: measure::Backend,
+        /// fail when a measured command exits unsuccessfully, instead of recording the
+        /// failure and flagging its calculations
+        #[structopt(long)]
+        fail_on_command_error: bool,
+        /// untimed runs of each command before timing starts
+        #[structopt(long, default_value = "1")]
+        warmup: usize,
+        /// timed runs of each command. defaults to at least 20, more for fast commands
+        #[structopt(long)]
+        runs: Option<usize>,
+        /// measure a metric up to n more times when its command fails, waiting longer before
+        /// each retry
+        #[structopt(long, default_value = "0")]
+        retries: usize,
+        /// print what would be measured, and where the results would be written, without
+        /// running anything
+        #[structopt(long)]
+        dry_run: bool,
+        /// directory to make this run's scratch directory in [default: the system temp directory]
+        #[structopt(long, parse(from_os_str))]
+        tmp_dir: Option<PathBuf>,
+        /// keep this run's scratch directory instead of removing it, for debugging
+        #[structopt(long)]
+        keep_tmp: bool,
+        /// directory to keep a copy of each project-metric pair's raw benchmarker output in,
+        /// named like its results file
+        #[structopt(long, parse(from_os_str))]
+        raw_dir: Option<PathBuf>,
+        /// compare each pair against the baseline results already in the results directory as
+        /// soon as it's measured, and stop with a non-zero exit at the first regression. pairs
+        /// already being measured still finish
+        #[structopt(long)]
+        fail_fast: bool,
+        /// don't report progress as each project-metric pair is measured
+        #[structopt(long)]
+        quiet: bool,
     },
     #[structopt(name = "calculate")]
     Calculate {
+        /// config file to read settings from. defaults to runner.toml if there is one
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
         #[structopt(parse(from_os_str))]
         #[structopt(short)]
-        results_dir: PathBuf,
+        results_dir: Option<PathBuf>,
+        #[structopt(parse(from_os_str))]
+        #[structopt(short)]
+        out_dir: Option<PathBuf>,
+        /// maximum allowed ratio of dev median to baseline median [default: 1.05]
+        #[structopt(long)]
+        median_threshold: Option<f64>,
+        /// maximum allowed ratio of dev stddev to baseline stddev [default: 1.20]
+        #[structopt(long)]
+        stddev_threshold: Option<f64>,
+        /// stddev to assume for a baseline measured with none, as a fraction of its mean
+        #[structopt(long, default_value = "0.02")]
+        min_stddev: f64,
+        /// smallest absolute change in seconds that can count as a regression or improvement
+        #[structopt(long, default_value = "0")]
+        min_abs_delta: f64,
+        /// toml file of per-project, per-metric threshold overrides
+        #[structopt(long, parse(from_os_str))]
+        thresholds: Option<PathBuf>,
+        /// toml file of accepted regressions, which are reported but don't fail the run
+        #[structopt(long, parse(from_os_str))]
+        accepted: Option<PathBuf>,
+        /// accept regressions of this <project>/<metric> for this run. can be repeated
+        #[structopt(long)]
+        accept: Vec<accept::Acceptance>,
+        /// statistic to compare dev and baseline measurements by: mean, median, min, p95, or p99 [default: median]
+        #[structopt(long)]
+        statistic: Option<Statistic>,
+        /// how regressions are decided: ratio (against the thresholds), welch (t-test on the
+        /// raw times), bootstrap (resampling the raw times), or mann-whitney (rank test on the
+        /// raw times) [default: ratio]
+        #[structopt(long)]
+        test: Option<ComparisonTest>,
+        /// significance level for the welch and mann-whitney tests [default: 0.05]
+        #[structopt(long)]
+        alpha: Option<f64>,
+        /// adjust alpha for the number of pairs compared by the welch or mann-whitney test: none, bonferroni,
+        /// or bh (Benjamini-Hochberg) [default: none]
+        #[structopt(long)]
+        correction: Option<Correction>,
+        /// fraction of each measurement's times to drop from both ends before comparing, e.g. 0.1
+        #[structopt(long, default_value = "0")]
+        trim: f64,
+        /// coefficient of variation (stddev / mean) above which a measurement is too noisy to
+        /// trust. noisy calculations are still reported, but never fail the run.
+        #[structopt(long)]
+        max_cv: Option<f64>,
+        /// fraction of cpu time spent in the kernel, system / (user + system), above which a
+        /// measurement is suspect, e.g. 0.5. suspect calculations are still reported, but never
+        /// fail the run.
+        #[structopt(long)]
+        max_system_ratio: Option<f64>,
+        /// confidence level of the interval reported around each baseline mean, and of the
+        /// bootstrap's interval
+        #[structopt(long, default_value = "0.95")]
+        confidence: f64,
+        /// resamples the bootstrap test draws for each comparison
+        #[structopt(long, default_value = "10000")]
+        bootstrap_iterations: usize,
+        /// also read results from subdirectories of the results directory
+        #[structopt(long)]
+        recursive: bool,
+        /// commit that dev was measured on. defaults to the commit checked out in the working directory
+        #[structopt(long)]
+        commit: Option<String>,
+        /// sort calculations by project and metric, record the same time on every one, and only
+        /// record a commit given with --commit, so identical results always give byte-identical
+        /// output. for snapshot tests, not production runs
+        #[structopt(long)]
+        deterministic: bool,
+        /// time to record on every calculation instead of the current time, like
+        /// 2021-07-29T12:00:00Z. --deterministic records the unix epoch unless this is given
+        #[structopt(long)]
+        now: Option<DateTime<Utc>>,
+        /// compare dev against results from this branch instead of `baseline`
+        #[structopt(long)]
+        baseline_branch: Option<String>,
+        #[structopt(flatten)]
+        filter: Filter,
+        /// format to print calculations to stdout in: text, csv, markdown, junit, github, html, pretty,
+        /// prometheus, or influx.
+        /// defaults to text, with github annotations added when running in GitHub Actions.
+        #[structopt(long)]
+        output: Option<OutputFormat>,
+        /// when to color the pretty output: auto, always, or never
+        #[structopt(long, default_value = "auto")]
+        color: ColorChoice,
+        /// IANA timezone to show timestamps in for the text and markdown output, e.g.
+        /// Europe/Madrid. other formats are always UTC [default: UTC]
+        #[structopt(long)]
+        timezone: Option<Tz>,
+        /// append this run's calculations to a json-lines history file
+        #[structopt(long, parse(from_os_str))]
+        history: Option<PathBuf>,
+        /// don't let a metric's regressions fail the run when its verdict flipped in more than
+        /// half of its last n runs in the history file. needs --history
+        #[structopt(long)]
+        quarantine_window: Option<usize>,
+        /// report metrics whose compared statistic has trended worse over their last n runs in
+        /// the history file, this one included, to final_drift_<ts>.json. drift is reported
+        /// but never fails the run. needs --history
+        #[structopt(long)]
+        drift_window: Option<usize>,
+        /// what dev is compared against: branch (the baseline branch's results), rolling (the
+        /// last --window runs in the history file, which needs --history), or ewma (the same
+        /// runs weighted toward the most recent by --ewma-alpha) [default: branch]
+        #[structopt(long, default_value = "branch")]
+        baseline_mode: BaselineMode,
+        /// number of recent runs in the history file a rolling baseline is built from
+        #[structopt(long, default_value = "20")]
+        window: usize,
+        /// weight of the newest run in an ewma baseline, from just above 0 to 1. each older run
+        /// weighs 1 - alpha times the one after it
+        #[structopt(long, default_value = "0.3")]
+        ewma_alpha: f64,
+        /// store this run's calculations in a sqlite database, creating it if needed
+        #[cfg(feature = "sqlite")]
+        #[structopt(long, parse(from_os_str))]
+        db: Option<PathBuf>,
+        /// write the formatted calculations to this file instead of stdout
+        #[structopt(long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+        /// also print the n regressions furthest over their thresholds
+        #[structopt(long)]
+        top: Option<usize>,
+        /// print only the --top regressions instead of the full report
+        #[structopt(long, requires = "top")]
+        only_top: bool,
+        /// only show regressions in the report. the exit code and written results still
+        /// cover every calculation
+        #[structopt(long)]
+        only_regressions: bool,
+        /// number of regressions to tolerate before exiting with a non-zero status
+        #[structopt(long, default_value = "0")]
+        max_regressions: usize,
+        /// exit with a non-zero status when a baseline measurement has no dev results
+        #[structopt(long)]
+        fail_on_missing: bool,
+        /// print nothing but warnings unless the run fails, and then only the summary. with
+        /// --only-regressions, the regressions are printed before the summary
+        #[structopt(long)]
+        quiet: bool,
+        /// warn about dev results that have no baseline to be compared to
+        #[structopt(long)]
+        warn_on_new: bool,
+        /// Slack incoming webhook to post a summary of regressions to
+        #[structopt(long, env = "SLACK_WEBHOOK_URL", hide_env_values = true)]
+        slack_webhook: Option<String>,
+        /// post to Slack even when there are no regressions
+        #[structopt(long)]
+        slack_always: bool,
+        /// refuse to compare results measured on different hardware instead of warning
+        #[structopt(long)]
+        strict_hardware: bool,
+    },
+    /// show how every project-metric pair moved between two branches' results, without thresholds
+    #[structopt(name = "diff")]
+    Diff {
         #[structopt(parse(from_os_str))]
         #[structopt(short)]
-        out_dir: PathBuf,
+        results_dir: PathBuf,
+        /// branch to diff from
+        from: String,
+        /// branch to diff to
+        to: String,
+        /// statistic to compare the branches by: mean, median, min, p95, or p99
+        #[structopt(long, default_value = "median")]
+        statistic: Statistic,
+        #[structopt(flatten)]
+        filter: Filter,
+    },
+    /// print the latest calculations stored for a metric in a sqlite database, as csv
+    #[cfg(feature = "sqlite")]
+    #[structopt(name = "query")]
+    Query {
+        /// database written to with `calculate --db`
+        #[structopt(long, parse(from_os_str))]
+        db: PathBuf,
+        /// calculation metric to look up, e.g. median_parse_01_2000_simple_models.json
+        metric: String,
+        /// number of calculations to print, newest first
+        #[structopt(long, default_value = "10")]
+        last: usize,
+    },
+    /// check every results file in a directory, reporting all the problems found
+    #[structopt(name = "validate")]
+    Validate {
+        #[structopt(parse(from_os_str))]
+        results_dir: PathBuf,
+        /// also check results files in subdirectories
+        #[structopt(long)]
+        recursive: bool,
+    },
+    /// print the json schema results files are read with, for editor integration
+    #[structopt(name = "schema")]
+    Schema,
+    /// compare the results in two directories directly, treating the first as the baseline
+    #[structopt(name = "compare")]
+    Compare {
+        /// toml file of settings to use [default: runner.toml, if present]
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
+        /// results directory to compare against
+        #[structopt(parse(from_os_str))]
+        reference: PathBuf,
+        /// results directory to compare
+        #[structopt(parse(from_os_str), required_unless = "samples")]
+        candidate: Option<PathBuf>,
+        /// a single results file to compare instead of a directory, or - to read one from stdin.
+        /// name the project and metric it's for with --project and --metric
+        #[structopt(long, parse(from_os_str), conflicts_with = "candidate")]
+        samples: Option<PathBuf>,
+        /// statistic to compare the measurements by: mean, median, min, p95, or p99 [default: median]
+        #[structopt(long)]
+        statistic: Option<Statistic>,
+        /// how regressions are decided: ratio, welch, bootstrap, or mann-whitney [default: ratio]
+        #[structopt(long)]
+        test: Option<ComparisonTest>,
+        /// also read results from subdirectories of both directories
+        #[structopt(long)]
+        recursive: bool,
+        /// only compare the reference directory's results from this branch, for a directory
+        /// holding results from more than one
+        #[structopt(long)]
+        reference_branch: Option<String>,
+        /// only compare the candidate directory's results from this branch
+        #[structopt(long)]
+        candidate_branch: Option<String>,
+        #[structopt(flatten)]
+        filter: Filter,
+        /// format to print calculations to stdout in, like calculate's --output [default: text]
+        #[structopt(long)]
+        output: Option<OutputFormat>,
     },
 }
 
+// GitHub Actions always sets this variable to "true" for steps it runs.
+fn in_github_actions() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true")
+}
+
+// Logs go to stderr so they never mix with a report printed to stdout. Only
+// warnings are logged unless -v or RUST_LOG asks for more.
+fn init_logging(verbose: u8) {
+    let filter = match verbose {
+        0 => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+        1 => EnvFilter::new("runner=info"),
+        _ => EnvFilter::new("runner=debug"),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+}
+
+// Formats calculations for --output, defaulting to text. `color` only
+// applies to the pretty output.
+fn render(
+    calcs: &[Calculation],
+    output: Option<OutputFormat>,
+    timezone: Option<&Tz>,
+    color: bool,
+) -> Result<String, CalculateError> {
+    Ok(match output.unwrap_or(OutputFormat::Text) {
+        // when running in GitHub Actions, annotate regressions without
+        // hiding the usual output from the logs.
+        OutputFormat::Text if output.is_none() && in_github_actions() => {
+            [output::to_text(calcs, timezone), output::to_github(calcs)].concat()
+        }
+        OutputFormat::Text => output::to_text(calcs, timezone),
+        OutputFormat::Csv => output::to_csv(calcs)?,
+        OutputFormat::Markdown => output::to_markdown(calcs, timezone),
+        OutputFormat::Junit => output::to_junit(calcs),
+        OutputFormat::Github => output::to_github(calcs),
+        OutputFormat::Html => output::to_html(calcs),
+        OutputFormat::Prometheus => output::to_prometheus(calcs),
+        OutputFormat::Influx => output::to_influx(calcs),
+        OutputFormat::Pretty => output::to_pretty(calcs, color),
+    })
+}
+
+// A filter that names a project that doesn't exist is almost certainly a typo.
+fn warn_unknown_projects(filter: &Filter, known: &[String]) {
+    for p in filter.unknown_projects(known) {
+        eprintln!("Main: warning: no project named '{}' was found", p);
+    }
+}
+
 // enables proper useage of exit() in main.
 // https://doc.rust-lang.org/std/process/fn.exit.html#examples
 //
 // This is where all the printing should happen. Exiting happens
 // in main, and module functions should only return values.
 fn run_app() -> Result<i32, CalculateError> {
+    let args = Args::from_args();
+    init_logging(args.verbose);
+
     // match what the user inputs from the cli
-    match Opt::from_args() {
+    match args.cmd {
         // measure subcommand
         Opt::Measure {
+            config,
             projects_dir,
             branch_name,
+            filter,
+            jobs,
+            backend,
+            fail_on_command_error,
+            warmup,
+            runs,
+            retries,
+            dry_run,
+            tmp_dir,
+            keep_tmp,
+            raw_dir,
+            fail_fast,
+            quiet,
         } => {
-            // if there are any nonzero exit codes from the hyperfine runs,
-            // return the first one. otherwise return zero.
-            measure::measure(&projects_dir, &branch_name)
-                .or_else(|e| Err(CalculateError::CalculateIOError(e)))?
-                .iter()
-                .map(|status| status.code())
-                .flatten()
-                .filter(|code| *code != 0)
-                .collect::<Vec<i32>>()
-                .get(0)
-                .map_or(Ok(0), |x| {
-                    println!("Main: a child process exited with a nonzero status code.");
-                    Ok(*x)
-                })
+            let settings = measure::Settings {
+                fail_on_command_error,
+                warmup,
+                runs,
+                retries,
+                // pairs measured at once would interleave their output
+                show_output: jobs == 1,
+            };
+            settings.validate()?;
+
+            let cli = Config {
+                projects_dir,
+                projects: filter.projects,
+                exclude_projects: filter.exclude_projects,
+                metrics: filter.metrics,
+                ..Config::default()
+            };
+            let config = cli.or(Config::load(config.as_deref())?);
+            let projects_dir = Config::require(&config.projects_dir, "projects_dir")?;
+            let filter = config.filter();
+
+            let known = measure::project_dirs(&projects_dir)?
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect::<Vec<String>>();
+            warn_unknown_projects(&filter, &known);
+
+            let pairs = measure::pairs(&projects_dir, &filter, &config.env)?;
+            if dry_run {
+                print!("{}", output::plan_to_text(&branch_name, &pairs));
+                // an empty plan is almost always a filter that matches nothing
+                if pairs.is_empty() {
+                    eprintln!(
+                        "Main: nothing would be measured. check the project and metric filters"
+                    );
+                    return Ok(1);
+                }
+                return Ok(0);
+            }
+            // removed when it goes out of scope, whether measuring succeeds or not
+            let tmp =
+                measure::TmpDir::create(&tmp_dir.unwrap_or_else(std::env::temp_dir), keep_tmp)?;
+            if keep_tmp {
+                eprintln!("Main: keeping scratch files in {}", tmp.path().display());
+            }
+            // compared by the same thresholds and acceptances calculate would use
+            let fail_fast = if fail_fast {
+                let opts = config.calculate_options()?;
+                opts.validate()?;
+                let acceptances = match &config.accepted {
+                    Some(path) => accept::from_toml_file(path)?,
+                    None => vec![],
+                };
+                Some((opts, acceptances))
+            } else {
+                None
+            };
+            if let Some(raw_dir) = &raw_dir {
+                std::fs::create_dir_all(raw_dir)
+                    .map_err(|e| IOError::WriteErr(raw_dir.clone(), Some(e)))?;
+            }
+            // a full suite takes long enough to look hung without these. a bar
+            // only makes sense on a terminal, so CI logs get a line per pair.
+            let total = pairs.len();
+            let bar = (!quiet && std::io::stderr().is_terminal()).then(|| {
+                ProgressBar::new(total as u64)
+                    .with_style(ProgressStyle::with_template("[{pos}/{len}] {msg}").unwrap())
+            });
+            let on_start = |n: usize, project: &str, metric: &str| {
+                let msg = format!("measuring project {} / metric {}", project, metric);
+                match &bar {
+                    Some(bar) => {
+                        bar.set_position(n as u64);
+                        bar.set_message(msg);
+                    }
+                    None if !quiet => eprintln!("[{}/{}] {}", n, total, msg),
+                    None => (),
+                }
+            };
+            let results_dir = measure::results_directory(&projects_dir);
+            let stopped_by = std::sync::Mutex::new(vec![]);
+            let on_measured = |outfile: &str| -> Result<bool, CalculateError> {
+                let (opts, acceptances) = match &fail_fast {
+                    Some(fail_fast) => fail_fast,
+                    None => return Ok(false),
+                };
+                let run = outfile
+                    .strip_prefix(&format!("{}_", branch_name))
+                    .unwrap_or(outfile);
+                let mut calcs = calculate::pair_regressions(&results_dir, &branch_name, run, opts)?;
+                accept::apply(&mut calcs, acceptances, Utc::today().naive_utc());
+                let mut regressed: Vec<Calculation> =
+                    calcs.into_iter().filter(|c| c.gates()).collect();
+                let stop = !regressed.is_empty();
+                stopped_by.lock().unwrap().append(&mut regressed);
+                Ok(stop)
+            };
+            let measured = measure::measure(
+                &projects_dir,
+                &branch_name,
+                &pairs,
+                jobs,
+                backend.benchmarker(settings).as_ref(),
+                tmp.path(),
+                raw_dir.as_deref(),
+                &on_start,
+                &on_measured,
+            );
+            if let Some(bar) = bar {
+                bar.finish_and_clear();
+            }
+            let written = measured?;
+
+            let env_fingerprint =
+                metadata::env_fingerprint(pairs.iter().map(|(_, project, metric)| {
+                    (format!("{}_{}", metric.name, project), &metric.env)
+                }));
+            metadata::write(
+                &measure::results_directory(&projects_dir),
+                &branch_name,
+                env_fingerprint,
+            )?;
+
+            let stopped_by = stopped_by.into_inner().unwrap();
+            if !stopped_by.is_empty() {
+                print!("{}", output::to_text(&stopped_by, None));
+                eprintln!(
+                    "Main: stopped at the first regression, after measuring {} of {} pairs",
+                    written.len(),
+                    total
+                );
+                return Ok(1);
+            }
+            Ok(0)
         }
 
         // calculate subcommand
         Opt::Calculate {
+            config,
             results_dir,
             out_dir,
+            median_threshold,
+            stddev_threshold,
+            min_stddev,
+            min_abs_delta,
+            thresholds,
+            accepted,
+            accept,
+            statistic,
+            test,
+            alpha,
+            correction,
+            trim,
+            max_cv,
+            max_system_ratio,
+            confidence,
+            bootstrap_iterations,
+            recursive,
+            commit,
+            deterministic,
+            now,
+            baseline_branch,
+            filter,
+            output,
+            color,
+            timezone,
+            history,
+            quarantine_window,
+            drift_window,
+            baseline_mode,
+            window,
+            ewma_alpha,
+            #[cfg(feature = "sqlite")]
+            db,
+            output_file,
+            top,
+            only_top,
+            only_regressions,
+            max_regressions,
+            fail_on_missing,
+            quiet,
+            warn_on_new,
+            slack_webhook,
+            slack_always,
+            strict_hardware,
         } => {
+            let cli = Config {
+                results_dir,
+                out_dir,
+                median_threshold,
+                stddev_threshold,
+                thresholds,
+                accepted,
+                statistic,
+                test,
+                alpha,
+                correction,
+                baseline_branch,
+                projects: filter.projects,
+                exclude_projects: filter.exclude_projects,
+                metrics: filter.metrics,
+                ..Config::default()
+            };
+            let config = cli.or(Config::load(config.as_deref())?);
+            let results_dir = Config::require(&config.results_dir, "results_dir")?;
+            let out_dir = Config::require(&config.out_dir, "out_dir")?;
+
             // validate output directory and exit early if it won't work.
-            let md = metadata(&out_dir)
-                .expect("Main: Failed to read specified output directory metadata. Does it exist?");
+            let md = metadata(&out_dir).map_err(|e| IOError::ReadErr(out_dir.clone(), Some(e)))?;
             if !md.is_dir() {
                 eprintln!("Main: Output directory is not a directory");
                 return Ok(1);
             }
 
             // get all the calculations or gracefully show the user an exception
-            let calculations = calculate::regressions(&results_dir)?;
+            let opts = CalculateOptions {
+                min_stddev,
+                min_abs_delta,
+                confidence,
+                bootstrap_iterations,
+                trim,
+                max_cv,
+                max_system_ratio,
+                // a snapshot shouldn't change with the commit it's checked in on
+                commit: if deterministic {
+                    commit
+                } else {
+                    commit.or_else(|| measure::current_commit(Path::new(".")))
+                },
+                now: now.or_else(|| deterministic.then(|| DateTime::<Utc>::from(UNIX_EPOCH))),
+                recursive,
+                ..config.calculate_options()?
+            };
+            warn_unknown_projects(
+                &opts.filter,
+                &calculate::project_names(&results_dir, opts.recursive)?,
+            );
+
+            // timings from different hardware can't be compared meaningfully
+            let compared = metadata::ComparedMetadata {
+                baseline: metadata::read(&results_dir, opts.baseline_branch())?,
+                dev: metadata::read(&results_dir, "dev")?,
+            };
+            if let Some((baseline, dev)) = compared.hardware_mismatch() {
+                if strict_hardware {
+                    return Err(CalculateError::HardwareMismatchErr(baseline, dev));
+                }
+                eprintln!(
+                    "Main: WARNING: baseline and dev were measured on different hardware. results are unlikely to be meaningful.\n  baseline: {}\n  dev: {}",
+                    baseline, dev
+                );
+            }
+
+            // the quarantine, the rolling and ewma baselines, and drift are decided
+            // by earlier runs, so the history has to be read before this run is appended
+            let needs_history = quarantine_window.is_some()
+                || drift_window.is_some()
+                || baseline_mode != BaselineMode::Branch;
+            let past = match &history {
+                Some(path) if needs_history => history::read(path)?,
+                Some(_) => vec![],
+                None if needs_history => {
+                    return Err(CalculateError::MissingSettingErr("history".to_owned()))
+                }
+                None => vec![],
+            };
+            let quarantines = match quarantine_window {
+                Some(window) => history::quarantined(&past, window),
+                None => vec![],
+            };
+
+            let calculate::Comparison {
+                mut calculations,
+                missing,
+                new,
+            } = match baseline_mode {
+                BaselineMode::Branch => calculate::regressions(&results_dir, &opts)?,
+                mode => {
+                    let baselines = if mode == BaselineMode::Ewma {
+                        history::ewma_baselines(&past, opts.statistic, window, ewma_alpha)?
+                    } else {
+                        history::rolling_baselines(&past, opts.statistic.name(), window)
+                    };
+                    // a short history still makes a baseline, just a rougher one
+                    for (run, m) in baselines.iter().filter(|(_, m)| m.times.len() < window) {
+                        let key = calculate::MetricKey::from_run(run);
+                        eprintln!(
+                            "Main: the {} baseline for metric '{}' of project '{}' only has {} of {} runs",
+                            if mode == BaselineMode::Ewma { "ewma" } else { "rolling" },
+                            key.metric,
+                            key.project,
+                            m.times.len(),
+                            window
+                        );
+                    }
+                    calculate::regressions_against(&results_dir, &baselines, &opts)?
+                }
+            };
+            if deterministic {
+                calculations.sort_by_cached_key(|c| {
+                    (
+                        calculate::MetricKey::from_calculation(&c.metric),
+                        c.metric.clone(),
+                    )
+                });
+            }
+            for c in calculations.iter_mut() {
+                let key = calculate::MetricKey::from_calculation(&c.metric);
+                c.quarantined = quarantines.iter().any(|q| q.key == key);
+            }
+            let mut acceptances = accept;
+            if let Some(path) = &config.accepted {
+                acceptances.extend(accept::from_toml_file(path)?);
+            }
+            // acceptances expire by the recorded time, so a snapshot doesn't change as
+            // their dates pass
+            let today = opts.now.map_or_else(Utc::today, |now| now.date());
+            let (applied, expired) =
+                accept::apply(&mut calculations, &acceptances, today.naive_utc());
+
+            // a non-zero exit code if there are more regressions than we're
+            // willing to tolerate, or results we asked to never go missing
+            let regressed = calculations.iter().filter(|c| c.gates()).count();
+            let fails = regressed > max_regressions || (fail_on_missing && !missing.is_empty());
+            // a quiet run that passes only prints its warnings
+            let silent = quiet && !fails;
+            let show_report = !quiet || (fails && only_regressions);
 
             // print all calculations to stdout so they can be easily debugged
-            // via CI.
-            println!(":: All Calculations ::\n");
-            for c in &calculations {
-                println!("{:#?}\n", c);
+            // via CI, or consumed by other tools. Filtering what's shown doesn't
+            // change what's written to the output directory or the exit code.
+            let shown: Vec<Calculation> = calculations
+                .iter()
+                .filter(|c| c.regression || !only_regressions)
+                .cloned()
+                .collect();
+            let is_terminal = output_file.is_none() && std::io::stdout().is_terminal();
+            let no_color = std::env::var_os("NO_COLOR").is_some();
+            let report = render(
+                &shown,
+                output,
+                timezone.as_ref(),
+                color.enabled(is_terminal, no_color),
+            )?;
+            match output_file {
+                Some(path) => File::create(&path)
+                    .and_then(|mut f| f.write_all(report.as_bytes()))
+                    .map_err(|e| IOError::WriteErr(path.clone(), Some(e)))?,
+                None if only_top || !show_report => (),
+                None => print!("{}", report),
+            }
+            if let Some(n) = top.filter(|_| !silent) {
+                print!("{}", output::to_top(&calculations, n));
+            }
+            if only_regressions && show_report {
+                eprintln!(
+                    "Main: showing {} regressions of {} calculations",
+                    shown.len(),
+                    calculations.len()
+                );
             }
 
             // indented json string representation of the calculations array
             let json_calcs = serde_json::to_string_pretty(&calculations)
-                .expect("Main: Failed to serialize calculations to json");
+                .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
 
             // if there are any calculations, use the first timestamp, if there are none
             // just use the current time.
             let ts = calculations
                 .first()
-                .map_or_else(|| Utc::now(), |calc| calc.ts);
+                .map_or_else(|| opts.now.unwrap_or_else(Utc::now), |calc| calc.ts);
 
             // create the empty destination file, and write the json string
-            let outfile = &mut out_dir.into_os_string();
-            outfile.push("/final_calculations_");
-            outfile.push(ts.timestamp().to_string());
-            outfile.push(".json");
-
-            let mut f = File::create(outfile).expect("Main: Unable to create file");
-            f.write_all(json_calcs.as_bytes())
-                .expect("Main: Unable to write data");
-
-            // filter for regressions
-            let regressions: Vec<&Calculation> =
-                calculations.iter().filter(|c| c.regression).collect();
-
-            // return a non-zero exit code if there are regressions
-            match regressions[..] {
-                [] => {
-                    println!("congrats! no regressions :)");
-                    Ok(0)
+            let outfile = out_dir
+                .join(["final_calculations_", &ts.timestamp().to_string(), ".json"].join(""));
+
+            File::create(&outfile)
+                .and_then(|mut f| f.write_all(json_calcs.as_bytes()))
+                .map_err(|e| IOError::WriteErr(outfile.clone(), Some(e)))?;
+
+            // drift is judged over the earlier runs and this one
+            let drifts = match drift_window {
+                Some(window) => {
+                    let series: Vec<calculate::Calculation> =
+                        past.iter().chain(&calculations).cloned().collect();
+                    let drifts =
+                        history::drifting(&series, opts.statistic.name(), window, opts.alpha);
+                    let json_drifts = serde_json::to_string_pretty(&drifts)
+                        .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+                    let drift_outfile = out_dir
+                        .join(["final_drift_", &ts.timestamp().to_string(), ".json"].join(""));
+                    File::create(&drift_outfile)
+                        .and_then(|mut f| f.write_all(json_drifts.as_bytes()))
+                        .map_err(|e| IOError::WriteErr(drift_outfile.clone(), Some(e)))?;
+                    drifts
                 }
-                _ => {
-                    // print all calculations to stdout so they can be easily
-                    // debugged via CI.
-                    println!(":: Regressions Found ::\n");
-                    for r in regressions {
-                        println!("{:#?}\n", r);
+                None => vec![],
+            };
+
+            if let Some(path) = history {
+                history::append(&path, &calculations)?;
+            }
+            #[cfg(feature = "sqlite")]
+            if let Some(path) = db {
+                db::Database::open(&path)?.insert(&calculations)?;
+            }
+
+            // and the run's totals, for CI to act on without adding them up
+            let summary = summary::summarize(&calculations, &missing, &new);
+            let json_summary = serde_json::to_string_pretty(&summary)
+                .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+            let summary_outfile =
+                out_dir.join(["final_summary_", &ts.timestamp().to_string(), ".json"].join(""));
+            File::create(&summary_outfile)
+                .and_then(|mut f| f.write_all(json_summary.as_bytes()))
+                .map_err(|e| IOError::WriteErr(summary_outfile.clone(), Some(e)))?;
+
+            // record where each side was measured next to the calculations
+            let json_metadata = serde_json::to_string_pretty(&compared)
+                .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+            let metadata_outfile =
+                out_dir.join(["final_metadata_", &ts.timestamp().to_string(), ".json"].join(""));
+            File::create(&metadata_outfile)
+                .and_then(|mut f| f.write_all(json_metadata.as_bytes()))
+                .map_err(|e| IOError::WriteErr(metadata_outfile.clone(), Some(e)))?;
+
+            // a Slack outage shouldn't fail the build, so this only warns
+            if let Some(webhook) = slack_webhook {
+                if let Some(payload) = notify::slack_payload(&calculations, slack_always) {
+                    if let Err(e) = notify::post_slack(&webhook, &payload) {
+                        eprintln!("Main: warning: could not post to Slack: {}", e);
                     }
-                    Ok(1)
                 }
             }
+
+            // noisy calculations are reported above but can't gate the run
+            let noisy = calculations.iter().filter(|c| c.noisy).count();
+            if noisy > 0 && !silent {
+                eprintln!(
+                    "Main: {} of {} calculations were too noisy to count toward regressions",
+                    noisy,
+                    calculations.len()
+                );
+            }
+
+            // a busy machine slows everything down, not just the change under test
+            let suspect = calculations.iter().filter(|c| c.suspect).count();
+            if suspect > 0 {
+                eprintln!(
+                    "Main: warning: {} of {} calculations had a suspiciously high share of system time",
+                    suspect,
+                    calculations.len()
+                );
+            }
+
+            // a command that failed while being measured was timed doing something
+            // other than its job, so its numbers can't gate the run either
+            let failed = calculations.iter().filter(|c| c.failed).count();
+            if failed > 0 {
+                eprintln!(
+                    "Main: warning: {} of {} calculations measured a command that failed",
+                    failed,
+                    calculations.len()
+                );
+            }
+
+            // quarantined metrics are reported as usual but can't gate the run
+            for q in quarantines.iter().filter(|_| !silent) {
+                eprintln!(
+                    "Main: quarantined metric '{}' of project '{}': its verdict flipped {} times in {} runs",
+                    q.key.metric, q.key.project, q.flips, q.runs
+                );
+            }
+
+            // drift is too gradual for any one run to regress, so it's reported apart
+            for d in drifts.iter().filter(|_| !silent) {
+                eprintln!(
+                    "Main: drifting metric '{}' of project '{}': {} per run over {} runs (p = {:.4})",
+                    d.metric,
+                    d.project,
+                    d.pct_per_run
+                        .map_or(format!("{:+.6}", d.slope), |pct| format!("{:+.2}%", pct)),
+                    d.runs,
+                    d.p_value
+                );
+            }
+
+            // accepted regressions are reported too, so they don't go unnoticed
+            for a in applied.iter().filter(|_| !silent) {
+                eprintln!(
+                    "Main: accepted regression in metric '{}' of project '{}'{}{}",
+                    a.key.metric,
+                    a.key.project,
+                    a.until.map_or("".to_owned(), |d| format!(" until {}", d)),
+                    a.reason
+                        .as_ref()
+                        .map_or("".to_owned(), |r| format!(": {}", r))
+                );
+            }
+            for a in &expired {
+                eprintln!(
+                    "Main: warning: the acceptance of metric '{}' of project '{}' expired on {}",
+                    a.key.metric,
+                    a.key.project,
+                    a.until.map_or("".to_owned(), |d| d.to_string())
+                );
+            }
+
+            // a baseline without dev results usually means the benchmark broke,
+            // which would otherwise look like nothing regressed
+            for key in &missing {
+                eprintln!(
+                    "Main: warning: no dev results for metric '{}' of project '{}'",
+                    key.metric, key.project
+                );
+            }
+
+            // new benchmarks are expected, but shouldn't go without a baseline for long
+            for key in new.iter().filter(|_| warn_on_new || !silent) {
+                eprintln!(
+                    "Main: {}new, no baseline: metric '{}' of project '{}'",
+                    if warn_on_new { "warning: " } else { "" },
+                    key.metric,
+                    key.project
+                );
+            }
+
+            if !silent {
+                eprint!("\n{}", output::summary_to_text(&summary));
+            }
+
+            if fails {
+                Ok(1)
+            } else {
+                Ok(0)
+            }
+        }
+
+        // diff subcommand
+        Opt::Diff {
+            results_dir,
+            from,
+            to,
+            statistic,
+            filter,
+        } => {
+            warn_unknown_projects(&filter, &calculate::project_names(&results_dir, false)?);
+            let diffs = calculate::diff(&results_dir, &from, &to, statistic, &filter)?;
+            print!("{}", output::diff_to_text(&diffs));
+            Ok(0)
+        }
+
+        // query subcommand
+        #[cfg(feature = "sqlite")]
+        Opt::Query { db, metric, last } => {
+            let calculations = db::Database::open(&db)?.last(&metric, last)?;
+            print!("{}", output::to_csv(&calculations)?);
+            Ok(0)
+        }
+
+        // validate subcommand
+        Opt::Validate {
+            results_dir,
+            recursive,
+        } => {
+            let (checked, problems) = calculate::validate_results(&results_dir, recursive)?;
+            for problem in &problems {
+                eprintln!("{}\n", problem);
+            }
+            eprintln!(
+                "Main: checked {} results files, {} had problems",
+                checked,
+                problems.len()
+            );
+            Ok(if problems.is_empty() { 0 } else { 1 })
+        }
+
+        // schema subcommand
+        Opt::Schema => {
+            let schema = serde_json::to_string_pretty(&schemars::schema_for!(Measurements))
+                .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+            println!("{}", schema);
+            Ok(0)
+        }
+
+        // compare subcommand
+        Opt::Compare {
+            config,
+            reference,
+            candidate,
+            samples,
+            statistic,
+            test,
+            recursive,
+            reference_branch,
+            candidate_branch,
+            filter,
+            output,
+        } => {
+            let cli = Config {
+                statistic,
+                test,
+                projects: filter.projects,
+                exclude_projects: filter.exclude_projects,
+                metrics: filter.metrics,
+                ..Config::default()
+            };
+            let config = cli.or(Config::load(config.as_deref())?);
+            let opts = CalculateOptions {
+                recursive,
+                reference_branch,
+                candidate_branch,
+                ..config.calculate_options()?
+            };
+
+            let (comparison, candidate) = match (samples, candidate) {
+                // piped from another tool, like hyperfine's own export
+                (Some(samples), _) if samples.as_os_str() == "-" => {
+                    let source = PathBuf::from("<stdin>");
+                    let mut contents = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut contents)
+                        .map_err(|e| IOError::ReadErr(source.clone(), Some(e)))?;
+                    let comparison =
+                        calculate::compare_samples(&reference, &source, &contents, &opts)?;
+                    (comparison, source)
+                }
+                (Some(samples), _) => {
+                    let contents = calculate::read_results_file(&samples)?;
+                    let comparison =
+                        calculate::compare_samples(&reference, &samples, &contents, &opts)?;
+                    (comparison, samples)
+                }
+                (None, Some(candidate)) => (
+                    calculate::compare(&reference, &candidate, &opts)?,
+                    candidate,
+                ),
+                (None, None) => {
+                    return Err(CalculateError::MissingSettingErr("candidate".to_owned()))
+                }
+            };
+            let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            print!("{}", render(&comparison.calculations, output, None, color)?);
+            for key in &comparison.missing {
+                eprintln!(
+                    "Main: only in {}: metric '{}' of project '{}'",
+                    reference.display(),
+                    key.metric,
+                    key.project
+                );
+            }
+            for key in &comparison.new {
+                eprintln!(
+                    "Main: only in {}: metric '{}' of project '{}'",
+                    candidate.display(),
+                    key.metric,
+                    key.project
+                );
+            }
+            // regressions fail the comparison like they fail calculate
+            if comparison.calculations.iter().any(|c| c.gates()) {
+                Ok(1)
+            } else {
+                Ok(0)
+            }
         }
     }
 }
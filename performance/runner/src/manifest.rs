@@ -0,0 +1,234 @@
+use crate::exceptions::{CalculateError, IOError};
+use crate::measure::Metric;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// The manifest read from a project's directory, if it has one.
+pub const MANIFEST_FILE: &str = "benchmark.toml";
+
+// A project's own list of metrics to measure, for projects that need a
+// different dbt invocation than the default one. Commands are templates
+// where `{{ name }}` is replaced by the variable `name`: any variable in the
+// `vars` table, or `project`, which is always the project's name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub vars: HashMap<String, String>,
+    // env vars for every metric in the project
+    pub env: HashMap<String, String>,
+    // run untimed before and after each of the project's metrics is
+    // measured, e.g. to seed and clean up a database
+    pub setup: String,
+    pub teardown: String,
+    pub metrics: Vec<MetricTemplate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub prepare: String,
+    pub cmd: String,
+}
+
+impl Manifest {
+    pub fn from_toml_file(path: &Path) -> Result<Self, CalculateError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| IOError::BadFileContentsErr(path.to_path_buf(), Some(e)))?;
+
+        toml::from_str(&contents)
+            .map_err(|e| CalculateError::BadTOMLErr(path.to_path_buf(), Some(e)))
+    }
+
+    // Reads the manifest in `project_dir`, or `None` if it doesn't have one.
+    pub fn load(project_dir: &Path) -> Result<Option<Self>, CalculateError> {
+        let path = project_dir.join(MANIFEST_FILE);
+        if path.is_file() {
+            Manifest::from_toml_file(&path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Expands every metric's commands for `project`. `path` is only used to
+    // say which manifest a bad template came from. The manifest's env vars
+    // are set on top of `env`, so the project's win.
+    pub fn metrics(
+        &self,
+        project: &str,
+        path: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<Vec<Metric>, CalculateError> {
+        let mut vars = self.vars.clone();
+        vars.insert("project".to_owned(), project.to_owned());
+
+        self.metrics
+            .iter()
+            .map(|m| {
+                // results files are named `{branch}_{metric}_{project}.json`,
+                // so an underscore in the metric would be read as part of the project
+                if m.name.is_empty() || m.name.contains('_') {
+                    return Err(CalculateError::BadMetricNameErr(
+                        path.to_path_buf(),
+                        m.name.clone(),
+                    ));
+                }
+                Ok(Metric {
+                    name: m.name.clone(),
+                    prepare: expand(&m.prepare, &vars, path)?,
+                    cmd: expand(&m.cmd, &vars, path)?,
+                    env: with_env(env, &self.env),
+                    setup: expand(&self.setup, &vars, path)?,
+                    teardown: expand(&self.teardown, &vars, path)?,
+                })
+            })
+            .collect()
+    }
+}
+
+// Replaces each `{{ name }}` in `template` with its variable. Single braces
+// are left alone, since dbt's `--vars` takes a yaml dictionary.
+pub fn expand(
+    template: &str,
+    vars: &HashMap<String, String>,
+    path: &Path,
+) -> Result<String, CalculateError> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| {
+            CalculateError::BadTemplateErr(path.to_path_buf(), template.to_owned())
+        })?;
+        let name = after[..end].trim();
+        let value = vars.get(name).ok_or_else(|| {
+            CalculateError::UnknownTemplateVarErr(path.to_path_buf(), name.to_owned())
+        })?;
+        expanded.push_str(value);
+        rest = &after[end + 2..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// Every var in `env`, overridden by any of the same name in `overrides`.
+fn with_env(
+    env: &HashMap<String, String>,
+    overrides: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    env.iter()
+        .chain(overrides.iter())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+// The metrics for the project in `project_dir`: its manifest's, or the
+// default ones if it doesn't have a manifest. Every metric gets `env`, under
+// the manifest's own env vars.
+pub fn project_metrics(
+    project_dir: &Path,
+    project: &str,
+    defaults: &[Metric],
+    env: &HashMap<String, String>,
+) -> Result<Vec<Metric>, CalculateError> {
+    match Manifest::load(project_dir)? {
+        Some(manifest) => manifest.metrics(project, &project_dir.join(MANIFEST_FILE), env),
+        None => Ok(defaults
+            .iter()
+            .map(|m| Metric {
+                env: with_env(env, &m.env),
+                ..m.clone()
+            })
+            .collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measure::{metric, TmpDir};
+
+    #[test]
+    fn expands_templates() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            setup = "psql -f seed_{{ project }}.sql"
+
+            [vars]
+            target = "postgres"
+
+            [[metrics]]
+            name = "parse"
+            prepare = "rm -rf target/"
+            cmd = "dbt parse --target {{ target }} --vars '{name: {{project}}}'"
+            "#,
+        )
+        .unwrap();
+        let path = Path::new("a/benchmark.toml");
+
+        let metrics = manifest.metrics("a", path, &HashMap::new()).unwrap();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].name, "parse");
+        assert_eq!(metrics[0].prepare, "rm -rf target/");
+        assert_eq!(
+            metrics[0].cmd,
+            "dbt parse --target postgres --vars '{name: a}'"
+        );
+
+        let vars = HashMap::new();
+        match expand("dbt run --target {{ target }}", &vars, path) {
+            Err(CalculateError::UnknownTemplateVarErr(_, name)) => assert_eq!(name, "target"),
+            x => panic!("expected UnknownTemplateVarErr, got {:?}", x),
+        }
+        match expand("dbt run --target {{ target", &vars, path) {
+            Err(CalculateError::BadTemplateErr(_, template)) => {
+                assert_eq!(template, "dbt run --target {{ target")
+            }
+            x => panic!("expected BadTemplateErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn falls_back_to_default_metrics() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let root = tmp.path().to_path_buf();
+        let (plain, custom) = (root.join("plain"), root.join("custom"));
+        fs::create_dir_all(&plain).unwrap();
+        fs::create_dir_all(&custom).unwrap();
+        fs::write(
+            custom.join(MANIFEST_FILE),
+            "[env]\nDBT_TARGET = \"ci\"\n\n[[metrics]]\nname = \"compile\"\ncmd = \"dbt compile\"\n",
+        )
+        .unwrap();
+        let defaults = vec![Metric {
+            prepare: "rm -rf target/".to_owned(),
+            ..metric("parse", "dbt parse")
+        }];
+        let env: HashMap<String, String> = vec![
+            ("DBT_TARGET".to_owned(), "dev".to_owned()),
+            (
+                "DBT_PROFILES_DIR".to_owned(),
+                "../../project_config/".to_owned(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let plain = project_metrics(&plain, "plain", &defaults, &env);
+        let custom = project_metrics(&custom, "custom", &defaults, &env);
+
+        let plain = plain.unwrap();
+        assert_eq!(plain[0].cmd, "dbt parse");
+        assert_eq!(plain[0].env, env);
+        let custom = custom.unwrap();
+        assert_eq!(custom[0].name, "compile");
+        assert_eq!(custom[0].prepare, "");
+        // the project's own env vars win
+        assert_eq!(custom[0].env["DBT_TARGET"], "ci");
+        assert_eq!(custom[0].env["DBT_PROFILES_DIR"], "../../project_config/");
+    }
+}
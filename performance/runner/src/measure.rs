@@ -1,49 +1,315 @@
-use crate::exceptions::IOError;
+use crate::calculate::{Measurement, Measurements};
+use crate::exceptions::{CalculateError, IOError};
+use crate::filter::Filter;
+use crate::manifest;
+use rayon::prelude::*;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use std::process::{Command, ExitStatus};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // `Metric` defines a dbt command that we want to measure on both the
-// baseline and dev branches.
-#[derive(Debug, Clone)]
-struct Metric<'a> {
-    name: &'a str,
-    prepare: &'a str,
-    cmd: &'a str,
+// baseline and dev branches. An empty `prepare` runs nothing before each
+// timed run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub prepare: String,
+    pub cmd: String,
+    // set in the environment of every command run for the metric,
+    // overriding any of the same name inherited from the runner
+    pub env: HashMap<String, String>,
+    // shell commands run once, untimed, before and after the metric is
+    // measured. Empty runs nothing.
+    pub setup: String,
+    pub teardown: String,
 }
 
-impl Metric<'_> {
+// A tool that can time a metric's command. Everything downstream only sees
+// the `Measurement` it returns, so another tool can be swapped in without
+// touching how measurements are compared.
+pub trait Benchmarker: Sync {
+    // Times `metric` from inside the project at `project_dir`. `name` is
+    // unique to this run of this project-metric pair. Any scratch files go
+    // in `tmp_dir`, which is shared by every pair in the run.
+    fn measure(
+        &self,
+        project_dir: &Path,
+        tmp_dir: &Path,
+        name: &str,
+        metric: &Metric,
+    ) -> Result<Measurement, CalculateError>;
+
+    // Where the last `measure` of `name` left the tool's own output, if it
+    // keeps any, so it can be kept for analysis later.
+    fn raw_output(&self, _tmp_dir: &Path, _name: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+// The benchmarking tools that can be selected with `--backend`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Backend {
+    #[default]
+    Hyperfine,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hyperfine" => Ok(Backend::Hyperfine),
+            _ => Err(format!(
+                "unknown backend '{}'. expected one of: hyperfine",
+                s
+            )),
+        }
+    }
+}
+
+// How each metric's command is timed, independent of the backend timing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    // fail the measurement when a benchmarked command exits unsuccessfully.
+    // Otherwise the failure is recorded in the measurement so its
+    // calculations can be flagged.
+    pub fail_on_command_error: bool,
+    // untimed runs before timing starts, to warm filesystem caches
+    pub warmup: usize,
+    // exactly how many timed runs to take, or `None` to let the backend
+    // decide
+    pub runs: Option<usize>,
+    // times to measure a metric again after a failure that might be
+    // transient, before giving up on it
+    pub retries: usize,
+    // pass the measured command's output through. Only readable when one
+    // pair is measured at a time, since concurrent pairs would interleave.
+    pub show_output: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            fail_on_command_error: false,
+            warmup: 1,
+            runs: None,
+            retries: 0,
+            show_output: true,
+        }
+    }
+}
+
+impl Settings {
+    // A measurement needs at least one timed run to have any times at all.
+    pub fn validate(&self) -> Result<(), CalculateError> {
+        match self.runs {
+            Some(runs) if runs < 1 => Err(CalculateError::BadRunsErr(runs)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Backend {
+    pub fn benchmarker(&self, settings: Settings) -> Box<dyn Benchmarker> {
+        let benchmarker: Box<dyn Benchmarker> = match self {
+            Backend::Hyperfine => Box::new(HyperfineBackend { settings }),
+        };
+        if settings.retries == 0 {
+            benchmarker
+        } else {
+            Box::new(Retrying {
+                inner: benchmarker,
+                retries: settings.retries,
+                backoff: Duration::from_secs(2),
+            })
+        }
+    }
+}
+
+// Measures with another benchmarker, measuring again after a failure that
+// might not happen the next time, like a network blip. Waits `backoff`
+// before the first retry, and twice as long before each one after that.
+// Only the last attempt's measurement is kept.
+pub struct Retrying {
+    pub inner: Box<dyn Benchmarker>,
+    pub retries: usize,
+    pub backoff: Duration,
+}
+
+// Whether an attempt is worth retrying. A command that couldn't be started,
+// or that the shell couldn't find or run, will fail the same way every time,
+// as will results that can't be read.
+fn retryable(attempt: &Result<Measurement, CalculateError>) -> bool {
+    let not_runnable = |code: &Option<i32>| matches!(code, Some(126) | Some(127));
+    match attempt {
+        Ok(m) => m.failed() && !m.exit_codes.iter().any(not_runnable),
+        Err(CalculateError::CalculateIOError(IOError::CommandFailedErr(_, code))) => {
+            !not_runnable(code)
+        }
+        Err(_) => false,
+    }
+}
+
+impl Benchmarker for Retrying {
+    fn measure(
+        &self,
+        project_dir: &Path,
+        tmp_dir: &Path,
+        name: &str,
+        metric: &Metric,
+    ) -> Result<Measurement, CalculateError> {
+        let mut backoff = self.backoff;
+        let mut attempt = self.inner.measure(project_dir, tmp_dir, name, metric);
+        for retry in 1..=self.retries {
+            if !retryable(&attempt) {
+                break;
+            }
+            tracing::warn!(
+                "measuring {} failed, retrying in {:?} ({} of {})",
+                name,
+                backoff,
+                retry,
+                self.retries
+            );
+            thread::sleep(backoff);
+            backoff *= 2;
+            attempt = self.inner.measure(project_dir, tmp_dir, name, metric);
+        }
+        attempt
+    }
+
+    fn raw_output(&self, tmp_dir: &Path, name: &str) -> Option<PathBuf> {
+        self.inner.raw_output(tmp_dir, name)
+    }
+}
+
+// Times commands with hyperfine, reading back the json it exports.
+pub struct HyperfineBackend {
+    pub settings: Settings,
+}
+
+impl Benchmarker for HyperfineBackend {
+    fn measure(
+        &self,
+        project_dir: &Path,
+        tmp_dir: &Path,
+        name: &str,
+        metric: &Metric,
+    ) -> Result<Measurement, CalculateError> {
+        let export = tmp_dir.join(name);
+        let command = [&metric.cmd, " --profiles-dir ", "../../project_config/"].join("");
+
+        let mut hyperfine = project_command("hyperfine", project_dir, metric);
+        if !self.settings.fail_on_command_error {
+            // keeps timing through failures, and reports each run's exit code
+            hyperfine.arg("--ignore-failure");
+        }
+        if !metric.prepare.is_empty() {
+            hyperfine.arg("--prepare").arg(&metric.prepare);
+        }
+        if self.settings.show_output {
+            // this prevents hyperfine from capturing dbt's output.
+            // Noisy, but good for debugging when tests fail.
+            hyperfine.arg("--show-output");
+        }
+        match self.settings.runs {
+            Some(runs) => hyperfine.arg("--runs").arg(runs.to_string()),
+            // --min-runs defaults to 10
+            None => hyperfine.arg("--min-runs").arg("20"),
+        };
+        tracing::debug!("running {:?}", hyperfine);
+        let status = hyperfine
+            // warms filesystem caches by running the command first without counting it.
+            // alternatively we could clear them before each run
+            .arg("--warmup")
+            .arg(self.settings.warmup.to_string())
+            .arg(&command)
+            .arg("--export-json")
+            .arg(&export)
+            .status() // use spawn() here instead for more information
+            .map_err(|e| IOError::CommandErr(Some(e)))?;
+        if !status.success() {
+            return Err(IOError::CommandFailedErr(command, status.code()).into());
+        }
+
+        let contents = fs::read_to_string(&export)
+            .map_err(|e| IOError::BadFileContentsErr(export.clone(), Some(e)))?;
+        let measurements = serde_json::from_str::<Measurements>(&contents)
+            .map_err(|e| CalculateError::BadJSONErr(export.clone(), Some(e)))?;
+
+        // one command was timed, so there is exactly one result
+        let count = measurements.results.len();
+        measurements
+            .results
+            .into_iter()
+            .next()
+            .filter(|_| count == 1)
+            .map(Measurement::with_percentiles)
+            .ok_or(CalculateError::BadMeasurementCountErr(export, count))
+    }
+
+    fn raw_output(&self, tmp_dir: &Path, name: &str) -> Option<PathBuf> {
+        Some(tmp_dir.join(name))
+    }
+}
+
+// Starts `program` from inside the project at `project_dir`, with the
+// metric's env vars set.
+fn project_command(program: &str, project_dir: &Path, metric: &Metric) -> Command {
+    let mut command = Command::new(program);
+    command.current_dir(project_dir).envs(&metric.env);
+    command
+}
+
+// Runs a setup or teardown `hook` through the shell, failing if it does.
+fn run_hook(hook: &str, project_dir: &Path, metric: &Metric) -> Result<(), CalculateError> {
+    if hook.is_empty() {
+        return Ok(());
+    }
+    let mut command = project_command("sh", project_dir, metric);
+    command.arg("-c").arg(hook);
+    tracing::debug!("running {:?}", command);
+    let status = command.status().map_err(|e| IOError::CommandErr(Some(e)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(IOError::CommandFailedErr(hook.to_owned(), status.code()).into())
+    }
+}
+
+impl Metric {
     // Returns the proper filename for the hyperfine output for this metric.
-    fn outfile(&self, project: &str, branch: &str) -> String {
-        [branch, "_", self.name, "_", project, ".json"].join("")
+    pub fn outfile(&self, project: &str, branch: &str) -> String {
+        [branch, "_", &self.name, "_", project, ".json"].join("")
     }
 }
 
-// Calls hyperfine via system command, and returns all the exit codes for each hyperfine run.
-pub fn measure<'a>(
-    projects_directory: &PathBuf,
-    dbt_branch: &str,
-) -> Result<Vec<ExitStatus>, IOError> {
-    /*
-        Strategy of this function body:
-        1. Read all directory names in `projects_directory`
-        2. Pair `n` projects with `m` metrics for a total of n*m pairs
-        3. Run hyperfine on each project-metric pair
-    */
-
-    // To add a new metric to the test suite, simply define it in this list:
-    // TODO: This could be read from a config file in a future version.
-    let metrics: Vec<Metric> = vec![Metric {
-        name: "parse",
-        prepare: "rm -rf target/",
-        cmd: "dbt parse --no-version-check",
-    }];
+// A metric that runs `cmd` and nothing else, for tests in this and other
+// modules to adjust with struct update syntax.
+#[cfg(test)]
+pub fn metric(name: &str, cmd: &str) -> Metric {
+    Metric {
+        name: name.to_owned(),
+        prepare: "".to_owned(),
+        cmd: cmd.to_owned(),
+        env: HashMap::new(),
+        setup: "".to_owned(),
+        teardown: "".to_owned(),
+    }
+}
 
+// Returns the path and name of every project in `projects_directory`.
+pub fn project_dirs(projects_directory: &Path) -> Result<Vec<(PathBuf, String)>, IOError> {
     fs::read_dir(projects_directory)
-        .or_else(|e| Err(IOError::ReadErr(projects_directory.to_path_buf(), Some(e))))?
+        .map_err(|e| IOError::ReadErr(projects_directory.to_path_buf(), Some(e)))?
         .map(|entry| {
             let path = entry
-                .or_else(|e| Err(IOError::ReadErr(projects_directory.to_path_buf(), Some(e))))?
+                .map_err(|e| IOError::ReadErr(projects_directory.to_path_buf(), Some(e)))?
                 .path();
 
             let project_name: String = path
@@ -55,38 +321,635 @@ pub fn measure<'a>(
                 })?
                 .to_owned();
 
+            Ok((path, project_name))
+        })
+        .collect()
+}
+
+// Hyperfine is run from inside each project, and writes its results to
+// `../../results/`, which is this directory.
+pub fn results_directory(projects_directory: &Path) -> PathBuf {
+    projects_directory.join("..").join("results")
+}
+
+// The commit checked out in `dir`, or `None` if git isn't installed or
+// `dir` isn't in a git repository.
+pub fn current_commit(dir: &Path) -> Option<String> {
+    Command::new("git")
+        .current_dir(dir)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|sha| sha.trim().to_owned())
+        .filter(|sha| !sha.is_empty())
+}
+
+// A directory made for one run's scratch files, which is removed with
+// everything in it when the guard is dropped, even if the run failed.
+// Kept instead when `keep` is set, for debugging.
+pub struct TmpDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TmpDir {
+    // Creates a directory under `parent` named for this process, the current
+    // time, and how many this process has made, so concurrent and
+    // consecutive runs never share one.
+    pub fn create(parent: &Path, keep: bool) -> Result<Self, CalculateError> {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let path = parent.join(format!(
+            "runner_{}_{}_{}",
+            std::process::id(),
+            nanos,
+            CREATED.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).map_err(|e| IOError::WriteErr(path.clone(), Some(e)))?;
+        Ok(TmpDir { path, keep })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+// Every project-metric pair to measure, with each project's metrics taken
+// from its manifest, or the default ones if it doesn't have a manifest.
+// `env` is set for every metric, under any env vars from the manifest.
+pub fn pairs(
+    projects_directory: &Path,
+    filter: &Filter,
+    env: &HashMap<String, String>,
+) -> Result<Vec<(PathBuf, String, Metric)>, CalculateError> {
+    // To add a new metric to every project without a manifest, simply define
+    // it in this list:
+    let metrics: Vec<Metric> = vec![Metric {
+        name: "parse".to_owned(),
+        prepare: "rm -rf target/".to_owned(),
+        cmd: "dbt parse --no-version-check".to_owned(),
+        env: HashMap::new(),
+        setup: "".to_owned(),
+        teardown: "".to_owned(),
+    }];
+
+    Ok(project_dirs(projects_directory)?
+        .into_iter()
+        .filter(|(_, project_name)| filter.includes_project(project_name))
+        .map(|(path, project_name)| {
             // each project-metric pair we will run
-            let pairs = metrics
-                .iter()
-                .map(|metric| (path.clone(), project_name.clone(), metric))
-                .collect::<Vec<(PathBuf, String, &Metric<'a>)>>();
+            Ok(
+                manifest::project_metrics(&path, &project_name, &metrics, env)?
+                    .into_iter()
+                    .filter(|metric| filter.includes_metric(&metric.name))
+                    .map(|metric| (path.clone(), project_name.clone(), metric))
+                    .collect::<Vec<(PathBuf, String, Metric)>>(),
+            )
+        })
+        .collect::<Result<Vec<Vec<(PathBuf, String, Metric)>>, CalculateError>>()?
+        .concat())
+}
 
-            Ok(pairs)
+// Writes `contents` to a temporary file next to `path` before renaming it
+// into place, so a reader never sees a partly written file. If either step
+// fails, the temporary file is removed rather than left next to `path`.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), IOError> {
+    let name = path.file_name().map_or("".into(), |n| n.to_string_lossy());
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    fs::write(&tmp, contents)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| {
+            // the write is already failing, so there's nothing to add if
+            // the cleanup fails too
+            let _ = fs::remove_file(&tmp);
+            IOError::WriteErr(path.to_path_buf(), Some(e))
         })
-        .collect::<Result<Vec<Vec<(PathBuf, String, &Metric<'a>)>>, IOError>>()?
-        .concat()
-        .iter()
-        // run hyperfine on each pairing
-        .map(|(path, project_name, metric)| {
-            Command::new("hyperfine")
-                .current_dir(path)
-                // warms filesystem caches by running the command first without counting it.
-                // alternatively we could clear them before each run
-                .arg("--warmup")
-                .arg("1")
-                // --min-runs defaults to 10
-                .arg("--min-runs")
-                .arg("20")
-                .arg("--prepare")
-                .arg(metric.prepare)
-                .arg([metric.cmd, " --profiles-dir ", "../../project_config/"].join(""))
-                .arg("--export-json")
-                .arg(["../../results/", &metric.outfile(project_name, dbt_branch)].join(""))
-                // this prevents hyperfine from capturing dbt's output.
-                // Noisy, but good for debugging when tests fail.
-                .arg("--show-output")
-                .status() // use spawn() here instead for more information
-                .or_else(|e| Err(IOError::CommandErr(Some(e))))
+}
+
+// Measures every project-metric pair with the benchmarker, `jobs` at a
+// time, writing each measurement to the results directory, and the
+// benchmarker's own output for it to `raw_dir` when one is given. Returns
+// the paths written to the results directory. `on_start` is called as each
+// pair starts with how many have started so far, counting this one, and the
+// pair's project and metric. `on_measured` is called with each results
+// filename once it's written, and pairs that haven't started yet are
+// skipped once it returns true. Pairs already being measured still finish.
+#[allow(clippy::too_many_arguments)]
+pub fn measure(
+    projects_directory: &Path,
+    dbt_branch: &str,
+    pairs: &[(PathBuf, String, Metric)],
+    jobs: usize,
+    benchmarker: &dyn Benchmarker,
+    tmp_dir: &Path,
+    raw_dir: Option<&Path>,
+    on_start: &(dyn Fn(usize, &str, &str) + Sync),
+    on_measured: &(dyn Fn(&str) -> Result<bool, CalculateError> + Sync),
+) -> Result<Vec<PathBuf>, CalculateError> {
+    let results_directory = results_directory(projects_directory);
+    let started = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    let written = run_all(pairs, jobs, |(path, project_name, metric)| {
+        if stopped.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        on_start(
+            started.fetch_add(1, Ordering::SeqCst) + 1,
+            project_name,
+            &metric.name,
+        );
+        let _span = tracing::info_span!("measure", project = %project_name, metric = %metric.name)
+            .entered();
+        let outfile = metric.outfile(project_name, dbt_branch);
+        run_hook(&metric.setup, path, metric)?;
+        let measured = benchmarker.measure(path, tmp_dir, &outfile, metric);
+        // teardown runs even when measuring fails, but that failure is the
+        // one worth reporting
+        let torn_down = run_hook(&metric.teardown, path, metric);
+        let measurement = measured?;
+        torn_down?;
+
+        // untouched, so different comparisons can be run on it later
+        if let (Some(raw_dir), Some(raw)) = (raw_dir, benchmarker.raw_output(tmp_dir, &outfile)) {
+            let contents =
+                fs::read(&raw).map_err(|e| IOError::BadFileContentsErr(raw.clone(), Some(e)))?;
+            write_atomically(&raw_dir.join(&outfile), &contents)?;
+            tracing::info!("kept raw output in {}", raw_dir.join(&outfile).display());
+        }
+
+        let outpath = results_directory.join(&outfile);
+        let json = serde_json::to_string_pretty(&Measurements {
+            results: vec![measurement],
         })
+        .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+        // calculate, or --fail-fast from another pair, may be reading the
+        // results directory while it's written
+        write_atomically(&outpath, json.as_bytes())?;
+        tracing::info!("wrote {}", outpath.display());
+        if on_measured(&outfile)? {
+            stopped.store(true, Ordering::SeqCst);
+        }
+        Ok(Some(outpath))
+    })?;
+    Ok(written.into_iter().flatten().collect())
+}
+
+// Runs `f` on every item with at most `jobs` running at once, or one per cpu
+// when `jobs` is 0. Results are returned in the same order as the items no
+// matter which finishes first. Every item is run even if some fail, and the
+// first failure in item order is returned.
+fn run_all<P, T, F>(items: &[P], jobs: usize, f: F) -> Result<Vec<T>, CalculateError>
+where
+    P: Sync,
+    T: Send,
+    F: Fn(&P) -> Result<T, CalculateError> + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|e| IOError::ThreadPoolErr(Some(e)))?;
+
+    pool.install(|| items.par_iter().map(&f).collect::<Vec<_>>())
+        .into_iter()
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn needs_at_least_one_run() {
+        let runs = |runs| Settings {
+            runs,
+            ..Settings::default()
+        };
+
+        assert!(runs(None).validate().is_ok());
+        assert!(runs(Some(1)).validate().is_ok());
+        match runs(Some(0)).validate() {
+            Err(CalculateError::BadRunsErr(0)) => (),
+            x => panic!("expected BadRunsErr, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn no_commit_outside_a_repository() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+        let commit = current_commit(&dir);
+
+        // the temp dir could only be in a repository on a very strange machine
+        assert_eq!(commit, None);
+    }
+
+    // Pretends every command takes as long as its project's name.
+    struct StubBackend;
+
+    impl Benchmarker for StubBackend {
+        fn measure(
+            &self,
+            project_dir: &Path,
+            tmp_dir: &Path,
+            name: &str,
+            metric: &Metric,
+        ) -> Result<Measurement, CalculateError> {
+            let t = project_dir.file_name().unwrap().len() as f64;
+            fs::write(tmp_dir.join(name), format!("raw {}", t)).unwrap();
+            Ok(Measurement {
+                command: metric.cmd.to_owned(),
+                mean: t,
+                stddev: 0.0,
+                median: t,
+                user: t,
+                system: 0.0,
+                min: t,
+                max: t,
+                times: vec![t],
+                p95: None,
+                p99: None,
+                exit_codes: vec![],
+            })
+        }
+
+        fn raw_output(&self, tmp_dir: &Path, name: &str) -> Option<PathBuf> {
+            Some(tmp_dir.join(name))
+        }
+    }
+
+    #[test]
+    fn writes_a_measurement_per_pair() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let root = tmp.path().to_path_buf();
+        let projects = root.join("projects");
+        for p in ["a", "bb"].iter() {
+            fs::create_dir_all(projects.join(p)).unwrap();
+        }
+        fs::create_dir_all(results_directory(&projects)).unwrap();
+
+        let pairs = pairs(&projects, &Filter::default(), &HashMap::new()).unwrap();
+        let started = Mutex::new(vec![]);
+        let on_start = |n: usize, project: &str, metric: &str| {
+            started
+                .lock()
+                .unwrap()
+                .push(format!("{} {} {}", n, project, metric))
+        };
+        let (tmp, raw) = (root.join("tmp"), root.join("raw"));
+        fs::create_dir_all(&tmp).unwrap();
+        fs::create_dir_all(&raw).unwrap();
+        let written = measure(
+            &projects,
+            "dev",
+            &pairs,
+            2,
+            &StubBackend,
+            &tmp,
+            Some(&raw),
+            &on_start,
+            &|_| Ok(false),
+        );
+        let a = fs::read_to_string(root.join("results").join("dev_parse_a.json"));
+        let raw_a = fs::read_to_string(raw.join("dev_parse_a.json"));
+        let raw_files = fs::read_dir(&raw).unwrap().count();
+
+        assert_eq!(written.unwrap().len(), 2);
+        // the pairs can start in either order, but each is counted once
+        let mut started = started.into_inner().unwrap();
+        started.sort();
+        assert!(started == ["1 a parse", "2 bb parse"] || started == ["1 bb parse", "2 a parse"]);
+        let a: Measurements = serde_json::from_str(&a.unwrap()).unwrap();
+        assert_eq!(a.results[0].command, "dbt parse --no-version-check");
+        assert_eq!(a.results[0].median, 1.0);
+        // the benchmarker's output is copied as is, with no temporary files left
+        assert_eq!(raw_a.unwrap(), "raw 1");
+        assert_eq!(raw_files, 2);
+    }
+
+    #[test]
+    fn measures_every_metric_a_project_lists() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let root = tmp.path().to_path_buf();
+        let projects = root.join("projects");
+        fs::create_dir_all(projects.join("a")).unwrap();
+        fs::create_dir_all(results_directory(&projects)).unwrap();
+        fs::write(
+            projects.join("a").join(manifest::MANIFEST_FILE),
+            ["parse", "compile", "run"]
+                .iter()
+                .map(|m| format!("[[metrics]]\nname = \"{}\"\ncmd = \"dbt {}\"\n", m, m))
+                .collect::<String>(),
+        )
+        .unwrap();
+
+        let pairs = pairs(&projects, &Filter::default(), &HashMap::new()).unwrap();
+        let written = measure(
+            &projects,
+            "dev",
+            &pairs,
+            2,
+            &StubBackend,
+            &root,
+            None,
+            &|_, _, _| (),
+            &|_| Ok(false),
+        );
+        let results = ["parse", "compile", "run"]
+            .iter()
+            .map(|m| fs::read_to_string(root.join("results").join(format!("dev_{}_a.json", m))))
+            .collect::<Result<Vec<String>, _>>();
+
+        assert_eq!(written.unwrap().len(), 3);
+        let commands: Vec<String> = results
+            .unwrap()
+            .iter()
+            .map(|r| {
+                serde_json::from_str::<Measurements>(r).unwrap().results[0]
+                    .command
+                    .clone()
+            })
+            .collect();
+        assert_eq!(commands, ["dbt parse", "dbt compile", "dbt run"]);
+    }
+
+    #[test]
+    fn stops_when_asked_after_a_pair() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let root = tmp.path().to_path_buf();
+        let projects = root.join("projects");
+        for p in ["a", "b", "c"].iter() {
+            fs::create_dir_all(projects.join(p)).unwrap();
+        }
+        fs::create_dir_all(results_directory(&projects)).unwrap();
+
+        let pairs = pairs(&projects, &Filter::default(), &HashMap::new()).unwrap();
+        let measured = Mutex::new(vec![]);
+        let on_measured = |outfile: &str| {
+            measured.lock().unwrap().push(outfile.to_owned());
+            Ok(true)
+        };
+        let written = measure(
+            &projects,
+            "dev",
+            &pairs,
+            1,
+            &StubBackend,
+            &root,
+            None,
+            &|_, _, _| (),
+            &on_measured,
+        );
+        let results = fs::read_dir(root.join("results")).unwrap().count();
+
+        // one job measures a pair at a time, so the others are never started
+        assert_eq!(written.unwrap().len(), 1);
+        assert_eq!(results, 1);
+        assert_eq!(measured.into_inner().unwrap().len(), 1);
+    }
+
+    // Fails to measure every command.
+    struct FailingBackend;
+
+    impl Benchmarker for FailingBackend {
+        fn measure(
+            &self,
+            _project_dir: &Path,
+            _tmp_dir: &Path,
+            _name: &str,
+            metric: &Metric,
+        ) -> Result<Measurement, CalculateError> {
+            Err(IOError::CommandFailedErr(metric.cmd.clone(), Some(1)).into())
+        }
+    }
+
+    #[test]
+    fn runs_setup_and_teardown_around_measuring() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let root = tmp.path().to_path_buf();
+        let projects = root.join("projects");
+        let project = projects.join("a");
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(results_directory(&projects)).unwrap();
+        let pair = |setup: &str| {
+            vec![(
+                project.clone(),
+                "a".to_owned(),
+                Metric {
+                    setup: setup.to_owned(),
+                    teardown: "touch torn_down".to_owned(),
+                    ..metric("parse", "dbt parse")
+                },
+            )]
+        };
+
+        let failed_measure = measure(
+            &projects,
+            "dev",
+            &pair("touch set_up"),
+            1,
+            &FailingBackend,
+            &root,
+            None,
+            &|_, _, _| (),
+            &|_| Ok(false),
+        );
+        let set_up = project.join("set_up").is_file();
+        let torn_down = project.join("torn_down").is_file();
+        let _ = fs::remove_file(project.join("torn_down"));
+        let failed_setup = measure(
+            &projects,
+            "dev",
+            &pair("exit 3"),
+            1,
+            &StubBackend,
+            &root,
+            None,
+            &|_, _, _| (),
+            &|_| Ok(false),
+        );
+        let skipped = !root.join("results").join("dev_parse_a.json").exists();
+        let torn_down_after_setup = project.join("torn_down").is_file();
+
+        // teardown runs like a `finally`, and the measurement's failure is kept
+        assert!(set_up && torn_down);
+        match failed_measure {
+            Err(CalculateError::CalculateIOError(IOError::CommandFailedErr(cmd, Some(1)))) => {
+                assert_eq!(cmd, "dbt parse")
+            }
+            x => panic!("expected the measurement's failure, got {:?}", x),
+        }
+        // a failed setup fails the pair without measuring it
+        match failed_setup {
+            Err(CalculateError::CalculateIOError(IOError::CommandFailedErr(cmd, Some(3)))) => {
+                assert_eq!(cmd, "exit 3")
+            }
+            x => panic!("expected the setup's failure, got {:?}", x),
+        }
+        assert!(skipped);
+        assert!(!torn_down_after_setup);
+    }
+
+    // Fails with each of `failures` in turn, then measures like StubBackend.
+    struct FlakyBackend {
+        failures: Vec<Option<i32>>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Benchmarker for FlakyBackend {
+        fn measure(
+            &self,
+            project_dir: &Path,
+            tmp_dir: &Path,
+            name: &str,
+            metric: &Metric,
+        ) -> Result<Measurement, CalculateError> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.failures.get(call) {
+                Some(code) => Err(IOError::CommandFailedErr(metric.cmd.clone(), *code).into()),
+                None => StubBackend.measure(project_dir, tmp_dir, name, metric),
+            }
+        }
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        let metric = metric("parse", "dbt parse");
+        // whether measuring succeeded, and how many attempts it took
+        let attempt = |failures: Vec<Option<i32>>, retries| {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let retrying = Retrying {
+                inner: Box::new(FlakyBackend {
+                    failures,
+                    calls: Arc::clone(&calls),
+                }),
+                retries,
+                backoff: Duration::from_millis(0),
+            };
+            let result = retrying.measure(
+                Path::new("projects/a"),
+                &std::env::temp_dir(),
+                "dev_parse_a.json",
+                &metric,
+            );
+            (result.is_ok(), calls.load(Ordering::SeqCst))
+        };
+
+        assert_eq!(attempt(vec![Some(1), Some(2)], 2), (true, 3));
+        assert_eq!(attempt(vec![Some(1), Some(2), Some(1)], 2), (false, 3));
+        // command not found fails the same way every time
+        assert_eq!(attempt(vec![Some(127)], 3), (false, 1));
+    }
+
+    #[test]
+    fn cleans_up_after_failed_atomic_writes() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let path = tmp.path().join("results.json");
+
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        // a file can't be renamed over a directory that isn't empty
+        let blocked = tmp.path().join("blocked.json");
+        fs::create_dir_all(blocked.join("inside")).unwrap();
+        assert!(write_atomically(&blocked, b"contents").is_err());
+        assert!(!tmp.path().join(".blocked.json.tmp").exists());
+        assert!(!tmp.path().join(".results.json.tmp").exists());
+    }
+
+    #[test]
+    fn removes_tmp_dir_unless_kept() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let parent = tmp.path().to_path_buf();
+        let removed = TmpDir::create(&parent, false).unwrap();
+        let kept = TmpDir::create(&parent, true).unwrap();
+        let (removed_path, kept_path) = (removed.path().to_path_buf(), kept.path().to_path_buf());
+        fs::write(removed_path.join("export.json"), "{}").unwrap();
+        let existed = removed_path.is_dir() && removed_path != kept_path;
+        drop(removed);
+        drop(kept);
+        let (removed_gone, kept_there) = (!removed_path.exists(), kept_path.is_dir());
+
+        assert!(existed);
+        assert!(removed_gone);
+        assert!(kept_there);
+    }
+
+    #[test]
+    fn sets_env_vars_on_commands() {
+        let metric = Metric {
+            env: vec![
+                ("RUNNER_TEST_VAR".to_owned(), "from config".to_owned()),
+                ("HOME".to_owned(), "overridden".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+            ..metric("parse", "")
+        };
+
+        let out = project_command("sh", &std::env::temp_dir(), &metric)
+            .arg("-c")
+            .arg("printf '%s, %s' \"$RUNNER_TEST_VAR\" \"$HOME\"")
+            .output()
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out.stdout).unwrap(),
+            "from config, overridden"
+        );
+    }
+
+    #[test]
+    fn runs_every_item_in_order() {
+        let items: Vec<String> = (0..50).map(|i| format!("project_{}", i)).collect();
+        let calls = AtomicUsize::new(0);
+
+        let results = run_all(&items, 4, |item| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(item.to_uppercase())
+        })
+        .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 50);
+        assert_eq!(results[0], "PROJECT_0");
+        assert_eq!(results[49], "PROJECT_49");
+    }
+
+    #[test]
+    fn surfaces_failures_after_running_everything() {
+        let items: Vec<usize> = (0..10).collect();
+        let calls = AtomicUsize::new(0);
+
+        let result = run_all(&items, 2, |i| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            if *i % 3 == 2 {
+                Err(CalculateError::CalculateIOError(
+                    IOError::MissingFilenameErr(PathBuf::from(i.to_string())),
+                ))
+            } else {
+                Ok(*i)
+            }
+        });
+
+        assert_eq!(calls.load(Ordering::SeqCst), 10);
+        match result {
+            Err(CalculateError::CalculateIOError(IOError::MissingFilenameErr(p))) => {
+                assert_eq!(p, PathBuf::from("2"))
+            }
+            x => panic!("expected the first failure, got {:?}", x),
+        }
+    }
+}
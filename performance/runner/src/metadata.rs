@@ -0,0 +1,265 @@
+use crate::exceptions::{CalculateError, IOError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Where a run took place. Comparing measurements taken on different machines
+// is meaningless, so this is recorded with every run. Anything that can't be
+// looked up on the current platform is left as `None`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub hostname: Option<String>,
+    pub cpu_model: Option<String>,
+    pub cores: Option<usize>,
+    pub os: String,
+    pub total_ram_bytes: Option<u64>,
+    // identifies the env vars the commands were run with, without recording
+    // their values. `None` when none were set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_fingerprint: Option<String>,
+}
+
+impl RunMetadata {
+    // Looks up the current machine. Reads from /proc where it's available
+    // rather than pulling in a system information crate.
+    pub fn collect() -> Self {
+        let read = |path: &str| fs::read_to_string(path).ok();
+
+        RunMetadata {
+            hostname: read("/proc/sys/kernel/hostname")
+                .or_else(|| read("/etc/hostname"))
+                .or_else(|| std::env::var("HOSTNAME").ok())
+                .map(|h| h.trim().to_owned())
+                .filter(|h| !h.is_empty()),
+            cpu_model: read("/proc/cpuinfo").and_then(|s| cpu_model(&s)),
+            cores: std::thread::available_parallelism().ok().map(|n| n.get()),
+            os: [std::env::consts::OS, std::env::consts::ARCH].join("-"),
+            total_ram_bytes: read("/proc/meminfo").and_then(|s| total_ram_bytes(&s)),
+            env_fingerprint: None,
+        }
+    }
+}
+
+impl RunMetadata {
+    // The parts of the metadata that affect timings, normalized so that
+    // cosmetic differences in how they're reported don't count. The
+    // hostname is left out since CI runners get a new one every run.
+    pub fn fingerprint(&self) -> String {
+        let cpu = self
+            .cpu_model
+            .as_deref()
+            .unwrap_or("unknown cpu")
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase();
+        let cores = self.cores.map_or("unknown".to_owned(), |n| n.to_string());
+        format!("{}, {} cores, {}", cpu, cores, self.os)
+    }
+}
+
+// The metadata recorded for both sides of a comparison, written alongside
+// the final calculations. Either side is `None` if it was measured without
+// recording any.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComparedMetadata {
+    pub baseline: Option<RunMetadata>,
+    pub dev: Option<RunMetadata>,
+}
+
+impl ComparedMetadata {
+    // The baseline and dev fingerprints when they name different hardware.
+    // Without metadata for both sides there is nothing to compare.
+    pub fn hardware_mismatch(&self) -> Option<(String, String)> {
+        match (&self.baseline, &self.dev) {
+            (Some(baseline), Some(dev)) if baseline.fingerprint() != dev.fingerprint() => {
+                Some((baseline.fingerprint(), dev.fingerprint()))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Each branch's metadata is kept next to its results. The extension keeps
+// it from being read as a results file.
+pub fn path(results_directory: &Path, branch: &str) -> PathBuf {
+    results_directory.join([branch, ".metadata"].join(""))
+}
+
+// Records the current machine as the one `branch` was measured on, along
+// with the fingerprint of the env vars it was measured with.
+pub fn write(
+    results_directory: &Path,
+    branch: &str,
+    env_fingerprint: Option<String>,
+) -> Result<PathBuf, CalculateError> {
+    let path = path(results_directory, branch);
+    let metadata = RunMetadata {
+        env_fingerprint,
+        ..RunMetadata::collect()
+    };
+    let json = serde_json::to_string_pretty(&metadata)
+        .map_err(|e| CalculateError::JSONSerializeErr(Some(e)))?;
+    fs::write(&path, json).map_err(|e| IOError::WriteErr(path.clone(), Some(e)))?;
+    Ok(path)
+}
+
+// Reads the metadata `branch` was measured with, if any was recorded.
+pub fn read(results_directory: &Path, branch: &str) -> Result<Option<RunMetadata>, CalculateError> {
+    let path = path(results_directory, branch);
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| CalculateError::BadJSONErr(path, Some(e))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(IOError::BadFileContentsErr(path, Some(e)).into()),
+    }
+}
+
+// A stable hash of every env var set for each project-metric pair, which are
+// identified by `{metric}_{project}`. Values can hold credentials, so only
+// the hash is kept. Order doesn't matter, and `None` means no vars were set.
+pub fn env_fingerprint<'a, I>(envs: I) -> Option<String>
+where
+    I: IntoIterator<Item = (String, &'a HashMap<String, String>)>,
+{
+    let mut lines: Vec<String> = envs
+        .into_iter()
+        .flat_map(|(pair, env)| {
+            env.iter()
+                .map(move |(k, v)| format!("{} {}={}", pair, k, v))
+                .collect::<Vec<String>>()
+        })
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    lines.sort();
+
+    // 64-bit FNV-1a, since std's hasher isn't guaranteed to be the same
+    // across Rust versions
+    let hash = lines
+        .join("\n")
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    Some(format!("{:016x}", hash))
+}
+
+// The first `model name` in the contents of /proc/cpuinfo.
+fn cpu_model(cpuinfo: &str) -> Option<String> {
+    cpuinfo
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == "model name")
+        .map(|(_, value)| value.trim().to_owned())
+}
+
+// `MemTotal` from the contents of /proc/meminfo, which is reported in kB.
+fn total_ram_bytes(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| {
+            rest.trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .map(|kb| kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measure::TmpDir;
+
+    #[test]
+    fn parses_proc_files() {
+        let cpuinfo = "processor\t: 0\nvendor_id\t: GenuineIntel\nmodel name\t: Intel(R) Xeon(R) CPU @ 2.20GHz\n\nprocessor\t: 1\nmodel name\t: Intel(R) Xeon(R) CPU @ 2.20GHz\n";
+        assert_eq!(
+            cpu_model(cpuinfo),
+            Some("Intel(R) Xeon(R) CPU @ 2.20GHz".to_owned())
+        );
+        assert_eq!(cpu_model("processor\t: 0\n"), None);
+
+        let meminfo = "MemTotal:       16324532 kB\nMemFree:         1234567 kB\n";
+        assert_eq!(total_ram_bytes(meminfo), Some(16_324_532 * 1024));
+        assert_eq!(total_ram_bytes("MemFree: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn detects_hardware_mismatches() {
+        let machine = |cpu: &str, cores| RunMetadata {
+            hostname: Some("runner-1".to_owned()),
+            cpu_model: Some(cpu.to_owned()),
+            cores: Some(cores),
+            os: "linux-x86_64".to_owned(),
+            total_ram_bytes: None,
+            env_fingerprint: None,
+        };
+        let compare = |baseline, dev| ComparedMetadata { baseline, dev }.hardware_mismatch();
+
+        // only spacing, case, and hostname differ
+        let other_host = RunMetadata {
+            hostname: Some("runner-2".to_owned()),
+            ..machine("INTEL(R) Xeon(R)  CPU", 2)
+        };
+        assert_eq!(
+            compare(Some(machine("Intel(R) Xeon(R) CPU", 2)), Some(other_host)),
+            None
+        );
+
+        assert_eq!(
+            compare(
+                Some(machine("Intel(R) Xeon(R) CPU", 2)),
+                Some(machine("AMD EPYC 7763", 4))
+            ),
+            Some((
+                "intel(r) xeon(r) cpu, 2 cores, linux-x86_64".to_owned(),
+                "amd epyc 7763, 4 cores, linux-x86_64".to_owned()
+            ))
+        );
+        assert_eq!(compare(None, Some(machine("AMD EPYC 7763", 4))), None);
+    }
+
+    #[test]
+    fn fingerprints_env_vars() {
+        let env = |vars: &[(&str, &str)]| -> HashMap<String, String> {
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let a = env(&[("DBT_TARGET", "ci"), ("DBT_PROFILES_DIR", "../")]);
+        let b = env(&[("DBT_PROFILES_DIR", "../"), ("DBT_TARGET", "ci")]);
+        let other = env(&[("DBT_TARGET", "dev"), ("DBT_PROFILES_DIR", "../")]);
+        let fingerprint = |env| env_fingerprint(vec![("parse_a".to_owned(), env)]);
+
+        assert_eq!(fingerprint(&a), fingerprint(&b));
+        assert_ne!(fingerprint(&a), fingerprint(&other));
+        assert!(!fingerprint(&a).unwrap().contains("ci"));
+        assert_eq!(fingerprint(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn round_trips_through_results_directory() {
+        let tmp = TmpDir::create(&std::env::temp_dir(), false).unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        let written = write(&dir, "dev", Some("0123456789abcdef".to_owned())).unwrap();
+        let dev = read(&dir, "dev");
+        let baseline = read(&dir, "baseline");
+
+        assert_eq!(written, dir.join("dev.metadata"));
+        let expected = RunMetadata {
+            env_fingerprint: Some("0123456789abcdef".to_owned()),
+            ..RunMetadata::collect()
+        };
+        assert_eq!(dev.unwrap(), Some(expected));
+        assert_eq!(baseline.unwrap(), None);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::calculate::Calculation;
+use serde_json::json;
+use std::time::Duration;
+
+// How long to wait on Slack before giving up. A slow webhook shouldn't
+// hold up the build.
+const SLACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Builds the Slack incoming webhook payload summarizing the regressions.
+// Returns `None` when there is nothing worth sending: no regressions,
+// unless `always` is set.
+pub fn slack_payload(calcs: &[Calculation], always: bool) -> Option<String> {
+    let regressions: Vec<&Calculation> = calcs.iter().filter(|c| c.gates()).collect();
+
+    if regressions.is_empty() && !always {
+        return None;
+    }
+
+    let text = if regressions.is_empty() {
+        format!(
+            "No performance regressions found in {} calculations :tada:",
+            calcs.len()
+        )
+    } else {
+        let lines: String = regressions
+            .iter()
+            .map(|c| {
+                let pct = c
+                    .data
+                    .pct_change
+                    .map_or("n/a".to_owned(), |pct| format!("{:+.2}%", pct));
+                format!("\n• `{}`: {}", c.metric, pct)
+            })
+            .collect();
+        format!(
+            ":warning: {} performance regression(s) found:{}",
+            regressions.len(),
+            lines
+        )
+    };
+
+    Some(json!({ "text": text }).to_string())
+}
+
+// Posts the payload to the webhook. Errors are returned as messages for the
+// caller to warn with since Slack being down should never fail a run.
+pub fn post_slack(webhook: &str, payload: &str) -> Result<(), String> {
+    ureq::post(webhook)
+        .timeout(SLACK_TIMEOUT)
+        .set("Content-Type", "application/json")
+        .send_string(payload)
+        .map(|_| ())
+        .map_err(|e| format!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(metric: &str, regression: bool, pct_change: f64) -> Calculation {
+        Calculation {
+            regression,
+            ..Calculation::for_test(metric, 1.0, 1.0 + pct_change / 100.0)
+        }
+    }
+
+    #[test]
+    fn only_sends_regressions_unless_always() {
+        let passing = vec![calc("median_parse_a", false, 1.0)];
+        assert_eq!(slack_payload(&passing, false), None);
+        assert_eq!(
+            slack_payload(&passing, true).unwrap(),
+            r#"{"text":"No performance regressions found in 1 calculations :tada:"}"#
+        );
+
+        let failing = vec![
+            calc("median_parse_a", false, 1.0),
+            calc("median_parse_b", true, 12.5),
+        ];
+        assert_eq!(
+            slack_payload(&failing, false).unwrap(),
+            r#"{"text":":warning: 1 performance regression(s) found:\n• `median_parse_b`: +12.50%"}"#
+        );
+    }
+}
@@ -0,0 +1,1171 @@
+use crate::calculate::{Calculation, Direction, MetricDiff, MetricKey};
+use crate::exceptions::CalculateError;
+use crate::measure::Metric;
+use crate::summary::Summary;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// The formats calculations can be printed to stdout in. The json
+// file written to the output directory is unaffected by this choice.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Csv,
+    Markdown,
+    Junit,
+    Github,
+    Html,
+    Pretty,
+    Prometheus,
+    Influx,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            "markdown" => Ok(OutputFormat::Markdown),
+            "junit" => Ok(OutputFormat::Junit),
+            "github" => Ok(OutputFormat::Github),
+            "html" => Ok(OutputFormat::Html),
+            "pretty" => Ok(OutputFormat::Pretty),
+            "prometheus" => Ok(OutputFormat::Prometheus),
+            "influx" => Ok(OutputFormat::Influx),
+            _ => Err(format!(
+                "unknown output format '{}'. expected one of: text, csv, markdown, junit, github, html, pretty, prometheus, influx",
+                s
+            )),
+        }
+    }
+}
+
+// Whether the pretty renderer uses ANSI colors. `Auto` only colors output
+// going to a terminal, and respects the NO_COLOR convention.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color choice '{}'. expected one of: auto, always, never",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn enabled(&self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal && !no_color,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+// Renders a duration in seconds for people to read: whole milliseconds
+// under a second, and seconds to two places above. Machine-readable
+// formats keep the raw seconds.
+pub fn format_duration(secs: f64) -> String {
+    let ms = secs * 1000.0;
+    if ms.abs() >= 999.5 {
+        format!("{:.2} s", secs)
+    } else if ms.abs() >= 1.0 {
+        format!("{:.0} ms", ms)
+    } else {
+        format!("{:.2} ms", ms)
+    }
+}
+
+// Renders a coefficient of variation as a percentage of the mean.
+fn format_cv(cv: Option<f64>) -> String {
+    cv.map_or("n/a".to_owned(), |cv| format!("{:.1}%", cv * 100.0))
+}
+
+// Says how dev moved from baseline in the terms of what was compared: a
+// duration is slower or faster, a higher-is-better metric like a throughput
+// is lower or higher, and a stddev is higher or lower whichever the metric.
+fn describe_change(c: &Calculation) -> String {
+    let pct = c
+        .data
+        .pct_change
+        .map_or("n/a".to_owned(), |pct| format!("{:.2}%", pct.abs()));
+    let up = c.data.dev > c.data.baseline;
+    if c.metric.starts_with("stddev_") {
+        let word = if up { "higher" } else { "lower" };
+        return format!("has a {} {} stddev than baseline", pct, word);
+    }
+    let word = match (c.direction, up) {
+        (Direction::LowerIsBetter, true) => "slower",
+        (Direction::LowerIsBetter, false) => "faster",
+        (Direction::HigherIsBetter, true) => "higher",
+        (Direction::HigherIsBetter, false) => "lower",
+    };
+    format!("is {} {} than baseline", pct, word)
+}
+
+// Labels a regression that doesn't count with why, e.g. ` [accepted]`.
+fn skip_label(c: &Calculation) -> String {
+    c.skip_reason()
+        .map_or(String::new(), |reason| format!(" [{}]", reason))
+}
+
+// Formats a calculation's difference, which a zero baseline leaves without.
+fn format_difference(difference: Option<f64>) -> String {
+    difference.map_or("n/a".to_owned(), |d| format!("{:.4}", d))
+}
+
+// Renders a timestamp in `tz` for people to read, e.g.
+// `2021-07-29 14:00:00 CEST`.
+pub fn format_ts(ts: &DateTime<Utc>, tz: &Tz) -> String {
+    ts.with_timezone(tz)
+        .format("%Y-%m-%d %H:%M:%S %Z")
+        .to_string()
+}
+
+// A calculation's debug output, with its timestamp in `tz` if one is given.
+fn debug_calculation(c: &Calculation, tz: Option<&Tz>) -> String {
+    let debug = format!("{:#?}", c);
+    match tz {
+        Some(tz) => debug.replacen(
+            &format!("ts: {:?},", c.ts),
+            &format!("ts: {},", format_ts(&c.ts, tz)),
+            1,
+        ),
+        None => debug,
+    }
+}
+
+// Renders calculations for people reading CI logs. All calculations are
+// printed so they can be easily debugged, followed by any improvements,
+// the regressions that don't count along with why, and the regressions
+// that do. Timestamps are UTC unless a timezone is given.
+pub fn to_text(calcs: &[Calculation], tz: Option<&Tz>) -> String {
+    let mut out = String::from(":: All Calculations ::\n\n");
+    for c in calcs {
+        out.push_str(&format!("{}\n\n", debug_calculation(c, tz)));
+    }
+
+    let improvements: Vec<&Calculation> = calcs.iter().filter(|c| c.improvement).collect();
+    if !improvements.is_empty() {
+        out.push_str(":: Improvements Found ::\n\n");
+        for i in improvements {
+            out.push_str(&format!("{}\n\n", debug_calculation(i, tz)));
+        }
+    }
+
+    let skipped: Vec<(&str, &Calculation)> = calcs
+        .iter()
+        .filter_map(|c| Some((c.skip_reason()?, c)))
+        .collect();
+    if !skipped.is_empty() {
+        out.push_str(":: Regressions Not Counted ::\n\n");
+        for (reason, s) in skipped {
+            out.push_str(&format!("{}: {}\n\n", reason, debug_calculation(s, tz)));
+        }
+    }
+
+    let regressions: Vec<&Calculation> = calcs.iter().filter(|c| c.gates()).collect();
+    if regressions.is_empty() {
+        out.push_str("congrats! no regressions :)\n");
+    } else {
+        out.push_str(":: Regressions Found ::\n\n");
+        for r in regressions {
+            out.push_str(&format!("{}\n\n", debug_calculation(r, tz)));
+        }
+    }
+
+    out
+}
+
+// Renders one line per calculation for people running the suite locally.
+// Regressions that don't count are marked skipped and labeled with why.
+// With color, regressions are red, skipped ones yellow, improvements
+// green, and everything else dim.
+pub fn to_pretty(calcs: &[Calculation], color: bool) -> String {
+    const RED: &str = "\x1b[31m";
+    const GREEN: &str = "\x1b[32m";
+    const YELLOW: &str = "\x1b[33m";
+    const DIM: &str = "\x1b[2m";
+    const RESET: &str = "\x1b[0m";
+
+    calcs
+        .iter()
+        .map(|c| {
+            let (status, code) = if c.gates() {
+                ("REGRESSED", RED)
+            } else if c.regression {
+                ("SKIPPED", YELLOW)
+            } else if c.improvement {
+                ("IMPROVED", GREEN)
+            } else {
+                ("ok", DIM)
+            };
+            let change = c
+                .data
+                .pct_change
+                .map_or("n/a".to_owned(), |pct| format!("{:+.2}%", pct));
+            let cv = c
+                .data
+                .cv
+                .map_or("".to_owned(), |_| format!(", cv {}", format_cv(c.data.cv)));
+            let line = format!(
+                "{:<9} {} {} ({} -> {}{}){}",
+                status,
+                c.metric,
+                change,
+                format_duration(c.data.baseline),
+                format_duration(c.data.dev),
+                cv,
+                skip_label(c)
+            );
+            if color {
+                format!("{}{}{}\n", code, line, RESET)
+            } else {
+                format!("{}\n", line)
+            }
+        })
+        .collect()
+}
+
+// A flattened calculation, since csv rows can't hold nested structs.
+#[derive(Debug, Serialize)]
+struct CsvRow<'a> {
+    metric: &'a str,
+    regression: bool,
+    improvement: bool,
+    ts: String,
+    threshold: f64,
+    difference: Option<f64>,
+    pct_change: Option<f64>,
+    p_value: Option<f64>,
+    ci_low: Option<f64>,
+    ci_high: Option<f64>,
+    baseline: f64,
+    dev: f64,
+}
+
+// Renders calculations as csv with one row per calculation and a header row.
+// Timestamps are RFC3339 so they can be parsed downstream, and missing values
+// are empty cells rather than `n/a`, which is only for the human formats.
+pub fn to_csv(calcs: &[Calculation]) -> Result<String, CalculateError> {
+    let mut wtr = csv::Writer::from_writer(vec![]);
+    for c in calcs {
+        wtr.serialize(CsvRow {
+            metric: &c.metric,
+            regression: c.regression,
+            improvement: c.improvement,
+            ts: c.ts.to_rfc3339(),
+            threshold: c.data.threshold,
+            difference: c.data.difference,
+            pct_change: c.data.pct_change,
+            p_value: c.data.p_value,
+            ci_low: c.data.ci_low,
+            ci_high: c.data.ci_high,
+            baseline: c.data.baseline,
+            dev: c.data.dev,
+        })
+        .map_err(|e| CalculateError::BadCSVErr(Some(e)))?;
+    }
+
+    let bytes = wtr
+        .into_inner()
+        .map_err(|e| CalculateError::BadCSVErr(Some(e.into_error().into())))?;
+
+    // the csv writer only ever writes the utf8 strings we give it
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// The `n` regressions furthest over their thresholds, worst first.
+pub fn top_regressions(calcs: &[Calculation], n: usize) -> Vec<&Calculation> {
+    let mut regressions: Vec<&Calculation> = calcs.iter().filter(|c| c.regression).collect();
+    // a regression from a zero baseline is infinitely far over
+    let over = |c: &Calculation| c.data.difference.unwrap_or(f64::INFINITY) - c.data.threshold;
+    regressions.sort_by(|x, y| over(y).total_cmp(&over(x)));
+    regressions.truncate(n);
+    regressions
+}
+
+// Renders a short list of the worst regressions for people skimming a
+// large suite, labeling the ones that don't count with why.
+pub fn to_top(calcs: &[Calculation], n: usize) -> String {
+    let top = top_regressions(calcs, n);
+    if top.is_empty() {
+        return "congrats! no regressions :)\n".to_owned();
+    }
+
+    let mut out = format!(":: Top {} Regressions ::\n\n", top.len());
+    for (i, c) in top.iter().enumerate() {
+        let change = c
+            .data
+            .pct_change
+            .map_or("n/a".to_owned(), |pct| format!("{:+.2}%", pct));
+        out.push_str(&format!(
+            "{}. {} {} (threshold {:.2}, difference {}){}\n",
+            i + 1,
+            c.metric,
+            change,
+            c.data.threshold,
+            format_difference(c.data.difference),
+            skip_label(c)
+        ));
+    }
+
+    out
+}
+
+// Renders calculations as a GitHub-flavored markdown table for PR comments.
+// Regressions are marked, or labeled with why they don't count, and sorted
+// to the top, otherwise the input order
+// is kept. Given a timezone, the table is followed by when it was
+// calculated in that timezone.
+pub fn to_markdown(calcs: &[Calculation], tz: Option<&Tz>) -> String {
+    let mut sorted: Vec<&Calculation> = calcs.iter().collect();
+    sorted.sort_by_key(|c| !c.regression);
+
+    let mut out = String::from(
+        "| | metric | threshold | difference | change | baseline | dev | cv |\n\
+         |---|---|---:|---:|---:|---:|---:|---:|\n",
+    );
+    for c in sorted {
+        let marker = if c.gates() {
+            "⚠️"
+        } else if let Some(reason) = c.skip_reason() {
+            reason
+        } else if c.improvement {
+            "🚀"
+        } else {
+            ""
+        };
+        let change = c
+            .data
+            .pct_change
+            .map_or("n/a".to_owned(), |pct| format!("{:+.2}%", pct));
+        out.push_str(&format!(
+            "| {} | {} | {:.2} | {} | {} | {} | {} | {} |\n",
+            marker,
+            c.metric,
+            c.data.threshold,
+            format_difference(c.data.difference),
+            change,
+            format_duration(c.data.baseline),
+            format_duration(c.data.dev),
+            format_cv(c.data.cv)
+        ));
+    }
+    if let (Some(tz), Some(c)) = (tz, calcs.first()) {
+        out.push_str(&format!("\n_calculated at {}_\n", format_ts(&c.ts, tz)));
+    }
+
+    out
+}
+
+// Escapes the characters that can't appear literally in xml text or attributes.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Renders calculations as a JUnit XML test suite so CI systems can show
+// regressions as test failures. Each calculation is one test case, and
+// regressions that don't count are skipped test cases saying why.
+pub fn to_junit(calcs: &[Calculation]) -> String {
+    let failures = calcs.iter().filter(|c| c.gates()).count();
+    let skipped = calcs.iter().filter(|c| c.skip_reason().is_some()).count();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuite name=\"performance\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+        calcs.len(),
+        failures,
+        skipped
+    ));
+    for c in calcs {
+        let name = xml_escape(&c.metric);
+        if let Some(reason) = c.skip_reason() {
+            out.push_str(&format!(
+                "  <testcase classname=\"performance\" name=\"{}\">\n",
+                name
+            ));
+            out.push_str(&format!(
+                "    <skipped message=\"{} performance regression\"/>\n",
+                reason
+            ));
+            out.push_str("  </testcase>\n");
+        } else if c.regression {
+            out.push_str(&format!(
+                "  <testcase classname=\"performance\" name=\"{}\">\n",
+                name
+            ));
+            out.push_str(&format!(
+                "    <failure message=\"performance regression\">baseline: {}\nthreshold: {}\ndev: {}\ndifference: {}</failure>\n",
+                c.data.baseline,
+                c.data.threshold,
+                c.data.dev,
+                c.data.difference.map_or("n/a".to_owned(), |d| d.to_string())
+            ));
+            out.push_str("  </testcase>\n");
+        } else {
+            out.push_str(&format!(
+                "  <testcase classname=\"performance\" name=\"{}\"/>\n",
+                name
+            ));
+        }
+    }
+    out.push_str("</testsuite>\n");
+
+    out
+}
+
+// Minimal styling and click-to-sort for the html report. Kept inline so the
+// report is a single file that can be passed around and opened anywhere.
+const HTML_STYLE: &str = "<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.3em 0.8em; border-bottom: 1px solid #ddd; }
+th { cursor: pointer; text-align: left; }
+td.num { text-align: right; font-family: monospace; }
+tr.regression { background: #fdd; }
+tr.skipped { background: #ffd; }
+tr.improvement { background: #dfd; }
+</style>
+";
+
+const HTML_SCRIPT: &str = "<script>
+document.querySelectorAll('th').forEach(function (th, col) {
+  th.addEventListener('click', function () {
+    var body = th.closest('table').tBodies[0];
+    var asc = th.dataset.asc !== 'true';
+    th.dataset.asc = asc;
+    var key = function (row) {
+      var text = row.cells[col].textContent;
+      var n = parseFloat(text);
+      return isNaN(n) ? text : n;
+    };
+    Array.from(body.rows)
+      .sort(function (a, b) {
+        var x = key(a), y = key(b);
+        return (x < y ? -1 : x > y ? 1 : 0) * (asc ? 1 : -1);
+      })
+      .forEach(function (row) { body.appendChild(row); });
+  });
+});
+</script>
+";
+
+// Renders calculations as a self-contained html page with a summary of
+// the run and a table with regressions and improvements highlighted.
+// Regressions that don't count are highlighted apart and labeled with why.
+// Clicking a column header sorts by that column.
+pub fn to_html(calcs: &[Calculation]) -> String {
+    let regressed = calcs.iter().filter(|c| c.gates()).count();
+    let skipped = calcs.iter().filter(|c| c.skip_reason().is_some()).count();
+    let improved = calcs.iter().filter(|c| c.improvement).count();
+
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Performance report</title>\n",
+    );
+    out.push_str(HTML_STYLE);
+    out.push_str("</head>\n<body>\n<h1>Performance report</h1>\n");
+    out.push_str(&format!(
+        "<p>total: {} | regressed: {} | skipped: {} | improved: {}</p>\n",
+        calcs.len(),
+        regressed,
+        skipped,
+        improved
+    ));
+    out.push_str(
+        "<table>\n<thead>\n<tr><th>metric</th><th>threshold</th><th>difference</th><th>change</th><th>baseline</th><th>dev</th></tr>\n</thead>\n<tbody>\n",
+    );
+    for c in calcs {
+        let class = if c.gates() {
+            " class=\"regression\""
+        } else if c.regression {
+            " class=\"skipped\""
+        } else if c.improvement {
+            " class=\"improvement\""
+        } else {
+            ""
+        };
+        let change = c
+            .data
+            .pct_change
+            .map_or("n/a".to_owned(), |pct| format!("{:+.2}%", pct));
+        out.push_str(&format!(
+            "<tr{}><td>{}{}</td><td class=\"num\">{:.2}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{:.4}</td><td class=\"num\">{:.4}</td></tr>\n",
+            class,
+            xml_escape(&c.metric),
+            skip_label(c),
+            c.data.threshold,
+            format_difference(c.data.difference),
+            change,
+            c.data.baseline,
+            c.data.dev
+        ));
+    }
+    out.push_str("</tbody>\n</table>\n");
+    out.push_str(HTML_SCRIPT);
+    out.push_str("</body>\n</html>\n");
+
+    out
+}
+
+// Renders a GitHub Actions workflow command for each regression so they
+// surface inline in the checks UI. Regressions that count are errors, and
+// the ones that don't are warnings saying why. Non-regressions produce no
+// output.
+pub fn to_github(calcs: &[Calculation]) -> String {
+    calcs
+        .iter()
+        .filter(|c| c.regression)
+        .map(|c| {
+            let key = MetricKey::from_calculation(&c.metric);
+            match c.skip_reason() {
+                Some(reason) => format!(
+                    "::warning title=Perf regression ({})::{}/{} {}\n",
+                    reason,
+                    key.project,
+                    key.metric,
+                    describe_change(c)
+                ),
+                None => format!(
+                    "::error title=Perf regression::{}/{} {}\n",
+                    key.project,
+                    key.metric,
+                    describe_change(c)
+                ),
+            }
+        })
+        .collect()
+}
+
+// Escapes a Prometheus label value, which is written between double quotes.
+fn label_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Renders calculations in the Prometheus text exposition format, for a
+// node_exporter textfile collector to pick up. Each statistic gets its own
+// gauge family of dev values in seconds, like `dbt_perf_median_seconds`,
+// and `dbt_perf_regression` is 1 for every calculation that regressed.
+pub fn to_prometheus(calcs: &[Calculation]) -> String {
+    // calculation metrics are named `{statistic}_{metric}_{project}.json`
+    let parsed: Vec<(&str, MetricKey, &Calculation)> = calcs
+        .iter()
+        .map(|c| {
+            let (statistic, run) = c.metric.split_once('_').unwrap_or(("", &c.metric));
+            (statistic, MetricKey::from_run(run), c)
+        })
+        .collect();
+    let labels = |key: &MetricKey| {
+        format!(
+            "project=\"{}\",metric=\"{}\"",
+            label_escape(&key.project),
+            label_escape(&key.metric)
+        )
+    };
+
+    let mut out = String::new();
+    for (statistic, group) in &parsed
+        .iter()
+        .sorted_by_key(|(statistic, _, _)| *statistic)
+        .group_by(|(statistic, _, _)| *statistic)
+    {
+        let family = format!("dbt_perf_{}_seconds", statistic);
+        out.push_str(&format!(
+            "# HELP {} The {} of the dev measurement, in seconds.\n# TYPE {} gauge\n",
+            family, statistic, family
+        ));
+        for (_, key, c) in group {
+            out.push_str(&format!("{}{{{}}} {}\n", family, labels(key), c.data.dev));
+        }
+    }
+
+    out.push_str(
+        "# HELP dbt_perf_regression Whether the statistic regressed from baseline to dev.\n# TYPE dbt_perf_regression gauge\n",
+    );
+    for (statistic, key, c) in &parsed {
+        out.push_str(&format!(
+            "dbt_perf_regression{{{},statistic=\"{}\"}} {}\n",
+            labels(key),
+            statistic,
+            c.regression as u8
+        ));
+    }
+
+    out
+}
+
+// Escapes an InfluxDB line-protocol tag value, where commas, equals signs,
+// and spaces would otherwise end it.
+fn tag_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+// Renders calculations in the InfluxDB line protocol, for writing straight
+// to a bucket. Each project-metric pair gets a `dbt_perf` point at its
+// calculation time, in nanoseconds, with a field of dev's value for each
+// statistic compared and `regression` as 1 if any of them regressed.
+pub fn to_influx(calcs: &[Calculation]) -> String {
+    struct Point<'a> {
+        key: MetricKey,
+        ts: DateTime<Utc>,
+        fields: Vec<(&'a str, f64)>,
+        regression: bool,
+    }
+
+    // the calculations of one run of a pair share a timestamp
+    let mut points: Vec<Point> = vec![];
+    for c in calcs {
+        // calculation metrics are named `{statistic}_{metric}_{project}.json`
+        let (statistic, run) = c.metric.split_once('_').unwrap_or(("", &c.metric));
+        let key = MetricKey::from_run(run);
+        let i = match points.iter().position(|p| p.key == key && p.ts == c.ts) {
+            Some(i) => i,
+            None => {
+                points.push(Point {
+                    key,
+                    ts: c.ts,
+                    fields: vec![],
+                    regression: false,
+                });
+                points.len() - 1
+            }
+        };
+        points[i].fields.push((statistic, c.data.dev));
+        points[i].regression |= c.regression;
+    }
+
+    points
+        .iter()
+        .map(|p| {
+            let fields = p
+                .fields
+                .iter()
+                .map(|(statistic, dev)| format!("{}={}", tag_escape(statistic), dev))
+                .join(",");
+            format!(
+                "dbt_perf,project={},metric={} {},regression={}i {}\n",
+                tag_escape(&p.key.project),
+                tag_escape(&p.key.metric),
+                fields,
+                p.regression as u8,
+                p.ts.timestamp_nanos()
+            )
+        })
+        .collect()
+}
+
+// Renders a run's totals as the last thing in the log, so there is a
+// verdict without scrolling back through every calculation.
+pub fn summary_to_text(summary: &Summary) -> String {
+    let mut out = format!(
+        ":: Summary ::\n\ncompared: {}\nregressions: {}\nskipped regressions: {}\nimprovements: {}\ntoo noisy: {}\nmissing: {}\nnew: {}\n",
+        summary.compared,
+        summary.regressions,
+        summary.skipped,
+        summary.improvements,
+        summary.noisy,
+        summary.missing,
+        summary.new
+    );
+    if let Some(worst) = &summary.worst {
+        out.push_str(&format!(
+            "worst regression: {} ({:.2} sigma from baseline)\n",
+            worst.metric, worst.sigmas
+        ));
+    }
+    out
+}
+
+// Renders the movement between two branches, one line per project-metric
+// pair. Pairs that moved by more than a stddev are marked, as are pairs
+// that only one of the branches measured.
+pub fn diff_to_text(diffs: &[MetricDiff]) -> String {
+    let value = |v: Option<f64>| v.map_or("n/a".to_owned(), format_duration);
+    diffs
+        .iter()
+        .map(|d| {
+            let marker = match (d.from, d.to) {
+                (None, _) => "added",
+                (_, None) => "removed",
+                _ if d.moved => "moved",
+                _ => "",
+            };
+            let change = d
+                .pct_change
+                .map_or(String::new(), |pct| format!(" ({:+.2}%)", pct));
+            format!(
+                "{:<8}{} {}: {} -> {}{}\n",
+                marker,
+                d.metric,
+                d.project,
+                value(d.from),
+                value(d.to),
+                change
+            )
+        })
+        .collect()
+}
+
+// Describes what `measure` would run for each project-metric pair, and
+// where it would write the results. Env var values are left out since they
+// can hold credentials.
+pub fn plan_to_text(branch: &str, pairs: &[(PathBuf, String, Metric)]) -> String {
+    let header = format!(
+        "would measure {} project-metric pairs on branch '{}':\n",
+        pairs.len(),
+        branch
+    );
+    let pairs: String = pairs
+        .iter()
+        .map(|(_, project, metric)| {
+            let mut lines = vec![format!(
+                "  {} {} -> {}",
+                metric.name,
+                project,
+                metric.outfile(project, branch)
+            )];
+            let steps = [
+                ("setup", &metric.setup),
+                ("prepare", &metric.prepare),
+                ("command", &metric.cmd),
+                ("teardown", &metric.teardown),
+            ];
+            for (step, cmd) in steps.iter().filter(|(_, cmd)| !cmd.is_empty()) {
+                lines.push(format!("    {}: {}", step, cmd));
+            }
+            if !metric.env.is_empty() {
+                lines.push(format!(
+                    "    env: {}",
+                    metric.env.keys().sorted().join(", ")
+                ));
+            }
+            lines.join("\n") + "\n"
+        })
+        .collect();
+    header + &pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(metric: &str, baseline: f64, dev: f64) -> Calculation {
+        Calculation::for_test(metric, baseline, dev)
+    }
+
+    #[test]
+    fn renders_csv_rows() {
+        let calcs = vec![calc("median_parse_project", 1.0, 1.5)];
+
+        assert_eq!(
+            to_csv(&calcs).unwrap(),
+            "metric,regression,improvement,ts,threshold,difference,pct_change,p_value,ci_low,ci_high,baseline,dev\n\
+             median_parse_project,true,false,2021-07-29T12:00:00+00:00,1.05,1.5,50.0,,,,1.0,1.5\n"
+        );
+    }
+
+    #[test]
+    fn orders_top_regressions_worst_first() {
+        let calcs = vec![
+            calc("median_parse_a", 1.0, 1.1),
+            calc("median_parse_b", 1.0, 1.0),
+            calc("median_parse_c", 1.0, 1.5),
+            calc("median_parse_d", 1.0, 1.2),
+        ];
+
+        let top: Vec<&str> = top_regressions(&calcs, 2)
+            .iter()
+            .map(|c| &c.metric[..])
+            .collect();
+        assert_eq!(top, vec!["median_parse_c", "median_parse_d"]);
+        assert_eq!(top_regressions(&calcs, 10).len(), 3);
+
+        assert_eq!(
+            to_top(&calcs, 1),
+            ":: Top 1 Regressions ::\n\n\
+             1. median_parse_c +50.00% (threshold 1.05, difference 1.5000)\n"
+        );
+
+        let mut calcs = calcs;
+        calcs[2].failed = true;
+        assert_eq!(
+            to_top(&calcs, 1),
+            ":: Top 1 Regressions ::\n\n\
+             1. median_parse_c +50.00% (threshold 1.05, difference 1.5000) [failed]\n"
+        );
+    }
+
+    #[test]
+    fn renders_missing_differences_as_na() {
+        let calcs = vec![
+            calc("median_parse_a", 1.0, 1.5),
+            calc("median_parse_b", 0.0, 0.1),
+        ];
+
+        assert_eq!(
+            to_top(&calcs, 1),
+            ":: Top 1 Regressions ::\n\n\
+             1. median_parse_b n/a (threshold 1.05, difference n/a)\n"
+        );
+        // csv leaves every missing value empty so it still parses as numbers
+        assert!(to_csv(&calcs)
+            .unwrap()
+            .contains("median_parse_b,true,false,2021-07-29T12:00:00+00:00,1.05,,,,,,0.0,0.1\n"));
+        assert!(to_markdown(&calcs, None).contains("| median_parse_b | 1.05 | n/a | n/a |"));
+        assert!(to_junit(&calcs).contains("difference: n/a"));
+        assert!(to_html(&calcs).contains("<td class=\"num\">1.05</td><td class=\"num\">n/a</td>"));
+    }
+
+    #[test]
+    fn renders_pretty_with_and_without_color() {
+        let calcs = vec![
+            calc("median_parse_a", 1.0, 1.0),
+            calc("median_parse_b", 1.0, 1.5),
+            calc("median_parse_c", 1.0, 0.5),
+        ];
+
+        assert_eq!(
+            to_pretty(&calcs, false),
+            "ok        median_parse_a +0.00% (1.00 s -> 1.00 s)\n\
+             REGRESSED median_parse_b +50.00% (1.00 s -> 1.50 s)\n\
+             IMPROVED  median_parse_c -50.00% (1.00 s -> 500 ms)\n"
+        );
+        assert_eq!(
+            to_pretty(&calcs[1..2], true),
+            "\x1b[31mREGRESSED median_parse_b +50.00% (1.00 s -> 1.50 s)\x1b[0m\n"
+        );
+
+        let mut noisy = calc("median_parse_d", 1.0, 1.0);
+        noisy.data.cv = Some(0.1234);
+        assert_eq!(
+            to_pretty(&[noisy], false),
+            "ok        median_parse_d +0.00% (1.00 s -> 1.00 s, cv 12.3%)\n"
+        );
+
+        let mut accepted = calc("median_parse_e", 1.0, 1.5);
+        accepted.accepted = true;
+        assert_eq!(
+            to_pretty(&[accepted], true),
+            "\x1b[33mSKIPPED   median_parse_e +50.00% (1.00 s -> 1.50 s) [accepted]\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn formats_durations_by_magnitude() {
+        assert_eq!(format_duration(1.2934829), "1.29 s");
+        assert_eq!(format_duration(0.2934829), "293 ms");
+        // rounds up to a full second rather than printing 1000 ms
+        assert_eq!(format_duration(0.9996), "1.00 s");
+        assert_eq!(format_duration(0.9994), "999 ms");
+        assert_eq!(format_duration(0.00025), "0.25 ms");
+        assert_eq!(format_duration(0.0), "0.00 ms");
+    }
+
+    #[test]
+    fn resolves_color_choice() {
+        assert!(ColorChoice::Auto.enabled(true, false));
+        assert!(!ColorChoice::Auto.enabled(false, false));
+        assert!(!ColorChoice::Auto.enabled(true, true));
+        assert!(ColorChoice::Always.enabled(false, true));
+        assert!(!ColorChoice::Never.enabled(true, false));
+    }
+
+    #[test]
+    fn renders_markdown_with_regressions_first() {
+        let mut calcs = vec![
+            calc("median_parse_a", 1.0, 1.0),
+            calc("median_parse_b", 1.0, 1.5),
+            calc("median_parse_c", 1.0, 1.5),
+        ];
+        calcs[1].data.cv = Some(0.025);
+        calcs[2].suspect = true;
+
+        assert_eq!(
+            to_markdown(&calcs, None),
+            "| | metric | threshold | difference | change | baseline | dev | cv |\n\
+             |---|---|---:|---:|---:|---:|---:|---:|\n\
+             | ⚠️ | median_parse_b | 1.05 | 1.5000 | +50.00% | 1.00 s | 1.50 s | 2.5% |\n\
+             | suspect | median_parse_c | 1.05 | 1.5000 | +50.00% | 1.00 s | 1.50 s | n/a |\n\
+             |  | median_parse_a | 1.05 | 1.0000 | +0.00% | 1.00 s | 1.00 s | n/a |\n"
+        );
+    }
+
+    #[test]
+    fn renders_text_with_uncounted_regressions_apart() {
+        let mut calcs = vec![calc("median_parse_a", 1.0, 1.5)];
+        calcs[0].accepted = true;
+
+        let text = to_text(&calcs, None);
+        assert!(text.contains(":: Regressions Not Counted ::\n\naccepted: Calculation {"));
+        assert!(!text.contains(":: Regressions Found ::"));
+        assert!(text.ends_with("congrats! no regressions :)\n"));
+    }
+
+    #[test]
+    fn renders_timestamps_in_the_requested_timezone() {
+        let calcs = vec![calc("median_parse_a", 1.0, 1.0)];
+        let tz: Tz = "Europe/Madrid".parse().unwrap();
+        assert!("Europe/Nowhere".parse::<Tz>().is_err());
+
+        assert_eq!(format_ts(&calcs[0].ts, &tz), "2021-07-29 14:00:00 CEST");
+        let text = to_text(&calcs, Some(&tz));
+        assert!(text.contains("ts: 2021-07-29 14:00:00 CEST,"));
+        assert!(to_text(&calcs, None).contains("ts: 2021-07-29T12:00:00Z,"));
+        assert!(to_markdown(&calcs, Some(&tz))
+            .ends_with("\n_calculated at 2021-07-29 14:00:00 CEST_\n"));
+        // machine-readable formats stay in UTC
+        assert!(to_csv(&calcs)
+            .unwrap()
+            .contains("2021-07-29T12:00:00+00:00"));
+    }
+
+    #[test]
+    fn renders_junit_failures() {
+        let mut calcs = vec![
+            calc("median_parse_a", 1.0, 1.0),
+            calc("median_parse_<b>", 1.0, 1.5),
+            calc("median_parse_c", 1.0, 1.5),
+        ];
+        calcs[2].quarantined = true;
+
+        assert_eq!(
+            to_junit(&calcs),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="performance" tests="3" failures="1" skipped="1">
+  <testcase classname="performance" name="median_parse_a"/>
+  <testcase classname="performance" name="median_parse_&lt;b&gt;">
+    <failure message="performance regression">baseline: 1
+threshold: 1.05
+dev: 1.5
+difference: 1.5</failure>
+  </testcase>
+  <testcase classname="performance" name="median_parse_c">
+    <skipped message="quarantined performance regression"/>
+  </testcase>
+</testsuite>
+"#
+        );
+    }
+
+    #[test]
+    fn renders_html_summary_and_highlights() {
+        let mut calcs = vec![
+            calc("median_parse_a", 1.0, 1.0),
+            calc("median_parse_<b>", 1.0, 1.5),
+            calc("median_parse_c", 1.0, 0.5),
+            calc("median_parse_d", 1.0, 1.5),
+        ];
+        calcs[3].noisy = true;
+        let html = to_html(&calcs);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<p>total: 4 | regressed: 1 | skipped: 1 | improved: 1</p>"));
+        assert!(html.contains("<tr class=\"skipped\"><td>median_parse_d [noisy]</td>"));
+        assert!(html.contains("<tr class=\"regression\"><td>median_parse_&lt;b&gt;</td>"));
+        assert!(html.contains("<tr class=\"improvement\"><td>median_parse_c</td>"));
+        assert!(html.contains("<tr><td>median_parse_a</td>"));
+        assert!(html.contains("<script>"));
+    }
+
+    #[test]
+    fn renders_prometheus_families() {
+        let calcs = vec![
+            calc("median_parse_a.json", 1.0, 1.0),
+            calc("stddev_parse_a.json", 0.1, 0.1),
+            calc("median_parse_\"b\".json", 1.0, 1.5),
+        ];
+
+        assert_eq!(
+            to_prometheus(&calcs),
+            r#"# HELP dbt_perf_median_seconds The median of the dev measurement, in seconds.
+# TYPE dbt_perf_median_seconds gauge
+dbt_perf_median_seconds{project="a",metric="parse"} 1
+dbt_perf_median_seconds{project="\"b\"",metric="parse"} 1.5
+# HELP dbt_perf_stddev_seconds The stddev of the dev measurement, in seconds.
+# TYPE dbt_perf_stddev_seconds gauge
+dbt_perf_stddev_seconds{project="a",metric="parse"} 0.1
+# HELP dbt_perf_regression Whether the statistic regressed from baseline to dev.
+# TYPE dbt_perf_regression gauge
+dbt_perf_regression{project="a",metric="parse",statistic="median"} 0
+dbt_perf_regression{project="a",metric="parse",statistic="stddev"} 0
+dbt_perf_regression{project="\"b\"",metric="parse",statistic="median"} 1
+"#
+        );
+        assert_eq!(label_escape("a\\b\nc"), "a\\\\b\\nc");
+    }
+
+    #[test]
+    fn renders_influx_points() {
+        let calcs = vec![
+            calc("median_parse_a.json", 1.0, 1.02),
+            calc("stddev_parse_a.json", 0.1, 0.1),
+            calc("median_parse_b c.json", 1.0, 1.5),
+        ];
+
+        assert_eq!(
+            to_influx(&calcs),
+            "dbt_perf,project=a,metric=parse median=1.02,stddev=0.1,regression=0i 1627560000000000000
+dbt_perf,project=b\\ c,metric=parse median=1.5,regression=1i 1627560000000000000
+"
+        );
+        assert_eq!(tag_escape("a,b=c d"), "a\\,b\\=c\\ d");
+    }
+
+    #[test]
+    fn renders_summaries() {
+        let mut summary = Summary {
+            compared: 4,
+            regressions: 1,
+            skipped: 2,
+            improvements: 0,
+            noisy: 0,
+            missing: 1,
+            new: 0,
+            worst: None,
+        };
+        assert_eq!(
+            summary_to_text(&summary),
+            ":: Summary ::\n\ncompared: 4\nregressions: 1\nskipped regressions: 2\nimprovements: 0\ntoo noisy: 0\nmissing: 1\nnew: 0\n"
+        );
+
+        summary.worst = Some(crate::summary::WorstRegression {
+            metric: "median_parse_a.json".to_owned(),
+            sigmas: 4.5,
+        });
+        assert!(summary_to_text(&summary)
+            .ends_with("worst regression: median_parse_a.json (4.50 sigma from baseline)\n"));
+    }
+
+    #[test]
+    fn renders_diffs() {
+        let d = |project: &str, from, to, pct_change, moved| MetricDiff {
+            project: project.to_owned(),
+            metric: "parse".to_owned(),
+            from,
+            to,
+            pct_change,
+            moved,
+        };
+        let diffs = vec![
+            d("a", None, Some(1.0), None, false),
+            d("b", Some(1.0), Some(1.5), Some(50.0), true),
+            d("c", Some(1.0), None, None, false),
+            d("d", Some(1.0), Some(1.0), Some(0.0), false),
+        ];
+
+        assert_eq!(
+            diff_to_text(&diffs),
+            "added   parse a: n/a -> 1.00 s\n\
+             moved   parse b: 1.00 s -> 1.50 s (+50.00%)\n\
+             removed parse c: 1.00 s -> n/a\n\
+             \x20       parse d: 1.00 s -> 1.00 s (+0.00%)\n"
+        );
+    }
+
+    #[test]
+    fn renders_measurement_plans() {
+        let metric = |name: &str, setup: &str, env: Vec<(&str, &str)>| Metric {
+            prepare: "rm -rf target/".to_owned(),
+            env: env
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect(),
+            setup: setup.to_owned(),
+            ..crate::measure::metric(name, &format!("dbt {}", name))
+        };
+        let pairs = vec![
+            (
+                PathBuf::from("projects/a"),
+                "a".to_owned(),
+                metric("parse", "", vec![]),
+            ),
+            (
+                PathBuf::from("projects/b"),
+                "b".to_owned(),
+                metric(
+                    "compile",
+                    "./seed.sh",
+                    vec![("DBT_TARGET", "ci"), ("API_TOKEN", "secret")],
+                ),
+            ),
+        ];
+
+        assert_eq!(
+            plan_to_text("dev", &pairs),
+            "would measure 2 project-metric pairs on branch 'dev':
+  parse a -> dev_parse_a.json
+    prepare: rm -rf target/
+    command: dbt parse
+  compile b -> dev_compile_b.json
+    setup: ./seed.sh
+    prepare: rm -rf target/
+    command: dbt compile
+    env: API_TOKEN, DBT_TARGET
+"
+        );
+    }
+
+    #[test]
+    fn renders_github_annotations_for_regressions_only() {
+        let mut calcs = vec![
+            calc("median_parse_a", 1.0, 1.0),
+            calc("median_parse_b", 1.0, 1.5),
+            calc("median_parse_c", 1.0, 1.5),
+            calc("median_parse_d", 0.0, 0.5),
+        ];
+        calcs[2].accepted = true;
+
+        assert_eq!(
+            to_github(&calcs),
+            "::error title=Perf regression::b/parse is 50.00% slower than baseline\n\
+             ::warning title=Perf regression (accepted)::c/parse is 50.00% slower than baseline\n\
+             ::error title=Perf regression::d/parse is n/a slower than baseline\n"
+        );
+    }
+
+    #[test]
+    fn describes_changes_by_direction_and_statistic() {
+        let throughput = Calculation {
+            regression: true,
+            direction: Direction::HigherIsBetter,
+            ..calc("median_throughput_a", 100.0, 90.0)
+        };
+        let stddev = calc("stddev_parse_a", 0.1, 0.13);
+
+        assert_eq!(
+            to_github(&[throughput.clone(), stddev]),
+            "::error title=Perf regression::a/throughput is 10.00% lower than baseline\n\
+             ::error title=Perf regression::a/parse has a 30.00% higher stddev than baseline\n"
+        );
+        assert!(to_text(&[throughput], None).contains("direction: HigherIsBetter,"));
+    }
+}
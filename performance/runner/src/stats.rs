@@ -0,0 +1,682 @@
+// Statistics helpers used when comparing measurements. Hyperfine already
+// summarizes each run, but tests that compare distributions need to work
+// from the raw `times` directly.
+
+// Arithmetic mean. `None` for an empty slice.
+pub fn mean(xs: &[f64]) -> Option<f64> {
+    if xs.is_empty() {
+        None
+    } else {
+        Some(xs.iter().sum::<f64>() / xs.len() as f64)
+    }
+}
+
+// `numer / denom`, or `None` when there's no meaningful ratio: a zero
+// denominator, or one so close to zero that the result overflows.
+pub fn safe_ratio(numer: f64, denom: f64) -> Option<f64> {
+    Some(numer / denom).filter(|r| denom != 0.0 && r.is_finite())
+}
+
+// Unbiased sample variance. `None` with fewer than two values.
+pub fn variance(xs: &[f64]) -> Option<f64> {
+    if xs.len() < 2 {
+        return None;
+    }
+    let m = mean(xs)?;
+    Some(xs.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (xs.len() - 1) as f64)
+}
+
+// Middle value of the sample, or the mean of the two middle values.
+// `None` for an empty slice.
+pub fn median(xs: &[f64]) -> Option<f64> {
+    let sorted = sorted(xs);
+    let n = sorted.len();
+    match n {
+        0 => None,
+        _ if n % 2 == 1 => Some(sorted[n / 2]),
+        _ => Some((sorted[n / 2 - 1] + sorted[n / 2]) / 2.0),
+    }
+}
+
+// The `p` quantile of the sample, for `p` between 0 and 1, interpolating
+// linearly between the two closest ranks. A single time is every
+// percentile of itself. `None` for an empty slice.
+pub fn percentile(xs: &[f64], p: f64) -> Option<f64> {
+    let sorted = sorted(xs);
+    let rank = p * (sorted.len().checked_sub(1)? as f64);
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+}
+
+// Drops the lowest and highest `fraction` of the sample, rounding down, so
+// a single slow run can't drag the summary statistics around. The result
+// is sorted.
+pub fn trim(xs: &[f64], fraction: f64) -> Vec<f64> {
+    let sorted = sorted(xs);
+    let k = (sorted.len() as f64 * fraction).floor() as usize;
+    if 2 * k >= sorted.len() {
+        return sorted;
+    }
+    sorted[k..sorted.len() - k].to_vec()
+}
+
+fn sorted(xs: &[f64]) -> Vec<f64> {
+    let mut sorted = xs.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted
+}
+
+// The result of Welch's two-sample t-test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WelchResult {
+    pub t: f64,
+    pub df: f64,
+    // two-sided p-value
+    pub p_value: f64,
+}
+
+// Welch's t-test for two samples with possibly unequal variances. A positive
+// `t` means `a` has the larger mean. `None` when either sample has fewer than
+// two values, since there is no variance to estimate.
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<WelchResult> {
+    let (na, nb) = (a.len() as f64, b.len() as f64);
+    let (ma, mb) = (mean(a)?, mean(b)?);
+    let (va, vb) = (variance(a)?, variance(b)?);
+
+    let (sa, sb) = (va / na, vb / nb);
+    let se2 = sa + sb;
+
+    // with no variance on either side, the means either match or they don't.
+    if se2 == 0.0 {
+        let (t, p_value) = if ma == mb {
+            (0.0, 1.0)
+        } else {
+            ((ma - mb).signum() * f64::INFINITY, 0.0)
+        };
+        return Some(WelchResult {
+            t,
+            df: na + nb - 2.0,
+            p_value,
+        });
+    }
+
+    let t = (ma - mb) / se2.sqrt();
+    let df = se2.powi(2) / (sa.powi(2) / (na - 1.0) + sb.powi(2) / (nb - 1.0));
+
+    Some(WelchResult {
+        t,
+        df,
+        p_value: students_t_two_sided_p(t, df),
+    })
+}
+
+// The least-squares line through a series of values taken at evenly spaced
+// steps, like consecutive runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrendResult {
+    // change per step
+    pub slope: f64,
+    // two-sided p-value of the slope being zero
+    pub p_value: f64,
+}
+
+// Fits a line to `ys` over their index and tests whether its slope differs
+// from zero with a t-test on n - 2 degrees of freedom. `None` with fewer than
+// three values, since two always fit a line exactly.
+pub fn linear_trend(ys: &[f64]) -> Option<TrendResult> {
+    if ys.len() < 3 {
+        return None;
+    }
+    let n = ys.len() as f64;
+    let (mx, my) = ((n - 1.0) / 2.0, mean(ys)?);
+    let sxx: f64 = (0..ys.len()).map(|i| (i as f64 - mx).powi(2)).sum();
+    let sxy: f64 = ys
+        .iter()
+        .enumerate()
+        .map(|(i, y)| (i as f64 - mx) * (y - my))
+        .sum();
+    let slope = sxy / sxx;
+    let sse: f64 = ys
+        .iter()
+        .enumerate()
+        .map(|(i, y)| (y - (my + slope * (i as f64 - mx))).powi(2))
+        .sum();
+    let se = (sse / (n - 2.0) / sxx).sqrt();
+
+    // values exactly on a line either trend or they don't
+    let p_value = if se == 0.0 {
+        if slope == 0.0 {
+            1.0
+        } else {
+            0.0
+        }
+    } else {
+        students_t_two_sided_p(slope / se, n - 2.0)
+    };
+    Some(TrendResult { slope, p_value })
+}
+
+// Two-sided p-value of Student's t distribution with `df` degrees of freedom.
+pub fn students_t_two_sided_p(t: f64, df: f64) -> f64 {
+    regularized_incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+// The value below which a standard normal variable falls with probability `p`,
+// for `p` strictly between 0 and 1. Uses Acklam's rational approximation,
+// which is accurate to about 1e-9.
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.024_25;
+
+    // evaluates a polynomial with the coefficients given highest power first
+    let poly = |cs: &[f64], x: f64| cs.iter().fold(0.0, |acc, c| acc * x + c);
+
+    if p < P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        poly(&C, q) / (poly(&D, q) * q + 1.0)
+    } else if p <= 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        poly(&A, r) * q / (poly(&B, r) * r + 1.0)
+    } else {
+        -normal_quantile(1.0 - p)
+    }
+}
+
+// Two-sided confidence interval for the mean of a sample, using the normal
+// approximation. `None` with fewer than two samples since there is nothing
+// to estimate the spread from. A sample with no spread has an interval of
+// exactly its mean.
+pub fn confidence_interval(
+    mean: f64,
+    stddev: f64,
+    n: usize,
+    confidence: f64,
+) -> Option<(f64, f64)> {
+    if n < 2 {
+        return None;
+    }
+    let z = normal_quantile(0.5 + confidence / 2.0);
+    let margin = z * stddev / (n as f64).sqrt();
+    Some((mean - margin, mean + margin))
+}
+
+// Seed for the bootstrap's resampling, fixed so that the same times always
+// give the same interval.
+const BOOTSTRAP_SEED: u64 = 0x5eed_da7a;
+
+// SplitMix64. Resampling only needs a fast generator with no obvious
+// patterns, not a cryptographic one.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A uniform index below `n`. The modulo bias is negligible for samples
+    // of any size we'd measure.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+// Mean of a sample of `xs` drawn with replacement.
+fn resampled_mean(xs: &[f64], rng: &mut SplitMix64) -> f64 {
+    (0..xs.len()).map(|_| xs[rng.below(xs.len())]).sum::<f64>() / xs.len() as f64
+}
+
+// Percentile bootstrap confidence interval for the mean of `a` minus the
+// mean of `b`. Each iteration resamples both with replacement, and the
+// interval is the middle `confidence` of the resampled differences. `None`
+// when either sample has fewer than two values, since resampling a single
+// time only ever gives it back, or with no iterations.
+pub fn bootstrap_mean_difference(
+    a: &[f64],
+    b: &[f64],
+    iterations: usize,
+    confidence: f64,
+) -> Option<(f64, f64)> {
+    if a.len() < 2 || b.len() < 2 || iterations == 0 {
+        return None;
+    }
+    let mut rng = SplitMix64(BOOTSTRAP_SEED);
+    let differences: Vec<f64> = (0..iterations)
+        .map(|_| resampled_mean(a, &mut rng) - resampled_mean(b, &mut rng))
+        .collect();
+    let tail = (1.0 - confidence) / 2.0;
+    Some((
+        percentile(&differences, tail)?,
+        percentile(&differences, 1.0 - tail)?,
+    ))
+}
+
+// The result of the Mann-Whitney U test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MannWhitneyResult {
+    // pairs where the value from `a` is larger, with ties counting half. Half
+    // of all the pairs means neither sample tends to be larger.
+    pub u: f64,
+    // two-sided p-value
+    pub p_value: f64,
+}
+
+// Samples at least this small without ties get an exact p-value. Above it,
+// the normal approximation is close enough.
+const MANN_WHITNEY_EXACT_MAX: usize = 20;
+
+// The Mann-Whitney U test of whether values from `a` tend to be larger than
+// values from `b`, without assuming either is normally distributed. Tied
+// values share their mean rank. `None` when either sample is empty.
+pub fn mann_whitney_u_test(a: &[f64], b: &[f64]) -> Option<MannWhitneyResult> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let (na, nb) = (a.len(), b.len());
+    let n = na + nb;
+
+    let mut values: Vec<(f64, bool)> = a
+        .iter()
+        .map(|x| (*x, true))
+        .chain(b.iter().map(|x| (*x, false)))
+        .collect();
+    values.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // ranks start at 1. a run of ties from `start` up to `end` shares the
+    // mean of the ranks it covers.
+    let (mut rank_sum, mut tie_term, mut ties) = (0.0, 0.0, false);
+    let mut start = 0;
+    while start < n {
+        let end = (start..n)
+            .find(|&i| values[i].0 != values[start].0)
+            .unwrap_or(n);
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum += rank * values[start..end].iter().filter(|v| v.1).count() as f64;
+        let t = (end - start) as f64;
+        tie_term += t.powi(3) - t;
+        ties |= end - start > 1;
+        start = end;
+    }
+    let u = rank_sum - (na * (na + 1)) as f64 / 2.0;
+
+    let p_value = if !ties && na <= MANN_WHITNEY_EXACT_MAX && nb <= MANN_WHITNEY_EXACT_MAX {
+        mann_whitney_exact_p(u as usize, na, nb)
+    } else {
+        let (na, nb, n) = (na as f64, nb as f64, n as f64);
+        let mu = na * nb / 2.0;
+        let variance = na * nb / 12.0 * ((n + 1.0) - tie_term / (n * (n - 1.0)));
+        if variance <= 0.0 {
+            // every value is the same, so neither sample is larger
+            1.0
+        } else {
+            // with a continuity correction, since U only takes whole or half values
+            let z = ((u - mu).abs() - 0.5).max(0.0) / variance.sqrt();
+            erfc(z / std::f64::consts::SQRT_2).min(1.0)
+        }
+    };
+
+    Some(MannWhitneyResult { u, p_value })
+}
+
+// Two-sided p-value of `u` from the exact distribution of U for samples of
+// `na` and `nb` values with no ties, counting the orderings of the two
+// samples that give each U.
+fn mann_whitney_exact_p(u: usize, na: usize, nb: usize) -> f64 {
+    let max_u = na * nb;
+    // counts[i][j][k] is the number of orderings of i values from one sample
+    // and j from the other with U = k. Only the row for the previous i is kept.
+    let mut previous: Vec<Vec<f64>> = (0..=nb)
+        .map(|_| {
+            let mut counts = vec![0.0; max_u + 1];
+            counts[0] = 1.0;
+            counts
+        })
+        .collect();
+    for _ in 0..na {
+        let mut current: Vec<Vec<f64>> = vec![vec![0.0; max_u + 1]; nb + 1];
+        current[0][0] = 1.0;
+        for j in 1..=nb {
+            for k in 0..=max_u {
+                // the largest value is either from the first sample, beating
+                // all j of the other's, or from the second
+                let first = if k >= j { previous[j][k - j] } else { 0.0 };
+                current[j][k] = first + current[j - 1][k];
+            }
+        }
+        previous = current;
+    }
+
+    let counts = &previous[nb];
+    let total: f64 = counts.iter().sum();
+    let lower: f64 = counts[..=u].iter().sum();
+    let upper: f64 = counts[u..].iter().sum();
+    (2.0 * lower.min(upper) / total).min(1.0)
+}
+
+// The complementary error function, from the Chebyshev fit in Numerical
+// Recipes, which is accurate to about 1.2e-7 everywhere.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let result = t * poly.exp();
+    if x >= 0.0 {
+        result
+    } else {
+        2.0 - result
+    }
+}
+
+// Natural log of the gamma function via the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        // reflection formula
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |acc, (i, c)| {
+            acc + c / (x + i as f64 + 1.0)
+        });
+
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+// The regularized incomplete beta function I_x(a, b).
+fn regularized_incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+
+    // the continued fraction converges quickly on this side of the mean,
+    // otherwise use the symmetry I_x(a, b) = 1 - I_(1-x)(b, a)
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+// Lentz's method for the continued fraction of the incomplete beta function.
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 200;
+    const EPSILON: f64 = 3e-14;
+    const FLOOR: f64 = 1e-300;
+
+    let floor = |v: f64| if v.abs() < FLOOR { FLOOR } else { v };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / floor(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+
+        // even step
+        let aa = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / floor(1.0 + aa * d);
+        c = floor(1.0 + aa / c);
+        h *= d * c;
+
+        // odd step
+        let aa = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / floor(1.0 + aa * d);
+        c = floor(1.0 + aa / c);
+        let delta = d * c;
+        h *= delta;
+
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} is not close to {}", a, b);
+    }
+
+    #[test]
+    fn guards_ratios_against_zero_denominators() {
+        assert_eq!(safe_ratio(1.0, 4.0), Some(0.25));
+        assert_eq!(safe_ratio(0.0, 2.0), Some(0.0));
+        assert_eq!(safe_ratio(1.0, 0.0), None);
+        assert_eq!(safe_ratio(1.0, -0.0), None);
+        assert_eq!(safe_ratio(0.0, 0.0), None);
+        // near zero is fine until the ratio overflows
+        assert_eq!(safe_ratio(1e-300, 1e-300), Some(1.0));
+        assert_eq!(safe_ratio(1.0, 1e-310), None);
+        assert_eq!(safe_ratio(f64::NAN, 1.0), None);
+    }
+
+    #[test]
+    fn fits_linear_trends() {
+        let exact = linear_trend(&[1.0, 1.01, 1.02, 1.03]).unwrap();
+        assert_close(exact.slope, 0.01);
+        assert!(exact.p_value < 1e-9);
+
+        let flat = linear_trend(&[1.0, 1.0, 1.0]).unwrap();
+        assert_eq!((flat.slope, flat.p_value), (0.0, 1.0));
+
+        // a slow creep stands out from its noise, but noise alone doesn't
+        let creep = linear_trend(&[1.0, 1.012, 1.018, 1.031, 1.04, 1.049, 1.062]).unwrap();
+        assert!(creep.slope > 0.0 && creep.p_value < 0.001);
+        let noise = linear_trend(&[1.0, 1.02, 0.99, 1.01, 1.0, 0.98, 1.01]).unwrap();
+        assert!(noise.p_value > 0.05);
+
+        assert_eq!(linear_trend(&[1.0, 2.0]), None);
+    }
+
+    #[test]
+    fn t_distribution_matches_closed_forms() {
+        // with one degree of freedom the t distribution is Cauchy, and with
+        // two the cdf has a simple closed form.
+        for &t in [0.5_f64, 1.0, 2.0, 10.0].iter() {
+            let cauchy = 1.0 - 2.0 / std::f64::consts::PI * t.atan();
+            assert_close(students_t_two_sided_p(t, 1.0), cauchy);
+
+            let two_df = 1.0 - t / (2.0 + t * t).sqrt();
+            assert_close(students_t_two_sided_p(t, 2.0), two_df);
+        }
+    }
+
+    #[test]
+    fn trims_both_tails() {
+        let xs = [5.0, 1.0, 100.0, 2.0, 3.0, 4.0, 0.0, 6.0, 7.0, 8.0];
+        assert_eq!(trim(&xs, 0.1), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(trim(&xs, 0.0).len(), 10);
+        // too few values to trim anything at all
+        assert_eq!(trim(&[2.0, 1.0], 0.1), vec![1.0, 2.0]);
+
+        assert_eq!(median(&xs), Some(4.5));
+        assert_eq!(median(&[3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(&[]), None);
+    }
+
+    #[test]
+    fn interpolates_percentiles() {
+        let xs: Vec<f64> = (1..=101).rev().map(f64::from).collect();
+        assert_close(percentile(&xs, 0.95).unwrap(), 96.0);
+        assert_close(percentile(&xs, 0.99).unwrap(), 100.0);
+        assert_close(percentile(&[1.0, 2.0], 0.95).unwrap(), 1.95);
+        // a short sample still has a tail, however rough
+        assert_eq!(percentile(&[3.0], 0.99), Some(3.0));
+        assert_eq!(percentile(&[], 0.95), None);
+    }
+
+    #[test]
+    fn normal_quantile_matches_known_values() {
+        assert!((normal_quantile(0.5)).abs() < 1e-9);
+        assert!((normal_quantile(0.975) - 1.959_963_985).abs() < 1e-6);
+        assert!((normal_quantile(0.995) - 2.575_829_304).abs() < 1e-6);
+        assert!((normal_quantile(0.01) + 2.326_347_874).abs() < 1e-6);
+    }
+
+    #[test]
+    fn confidence_interval_handles_edge_cases() {
+        let (low, high) = confidence_interval(1.0, 0.5, 25, 0.95).unwrap();
+        assert!((low - (1.0 - 1.959_963_985 * 0.1)).abs() < 1e-6);
+        assert!((high - (1.0 + 1.959_963_985 * 0.1)).abs() < 1e-6);
+
+        assert_eq!(confidence_interval(1.0, 0.0, 25, 0.95), Some((1.0, 1.0)));
+        assert_eq!(confidence_interval(1.0, 0.5, 1, 0.95), None);
+    }
+
+    #[test]
+    fn bootstraps_mean_differences() {
+        let baseline = [1.0, 1.1, 0.9, 1.05, 0.95];
+        let dev = [1.2, 1.3, 1.1, 1.25, 1.15];
+
+        let (low, high) = bootstrap_mean_difference(&dev, &baseline, 2000, 0.95).unwrap();
+        assert!(low > 0.0 && low < 0.2, "low was {}", low);
+        assert!(high > 0.2 && high < 0.4, "high was {}", high);
+        // the same times always resample the same way
+        assert_eq!(
+            bootstrap_mean_difference(&dev, &baseline, 2000, 0.95),
+            Some((low, high))
+        );
+
+        let (low, high) = bootstrap_mean_difference(&baseline, &baseline, 2000, 0.95).unwrap();
+        assert!(low < 0.0 && high > 0.0);
+        // no spread on either side means every resample is the same
+        assert_eq!(
+            bootstrap_mean_difference(&[2.0, 2.0], &[1.0, 1.0], 10, 0.95),
+            Some((1.0, 1.0))
+        );
+
+        assert_eq!(bootstrap_mean_difference(&dev, &[1.0], 2000, 0.95), None);
+        assert_eq!(bootstrap_mean_difference(&dev, &baseline, 0, 0.95), None);
+    }
+
+    #[test]
+    fn mann_whitney_is_exact_for_small_samples() {
+        // every dev time is slower than every baseline time. of the 252
+        // orderings of two samples of five, only this one and its mirror are
+        // as extreme.
+        let baseline = [1.0, 1.1, 0.9, 1.05, 0.95];
+        let dev = [1.2, 1.3, 1.15, 1.25, 1.22];
+        let result = mann_whitney_u_test(&dev, &baseline).unwrap();
+        assert_eq!(result.u, 25.0);
+        assert_close(result.p_value, 2.0 / 252.0);
+        assert_eq!(mann_whitney_u_test(&baseline, &dev).unwrap().u, 0.0);
+
+        // a single value on each side can never be significant
+        let result = mann_whitney_u_test(&[2.0], &[1.0]).unwrap();
+        assert_eq!(result.u, 1.0);
+        assert_close(result.p_value, 1.0);
+
+        assert_eq!(mann_whitney_u_test(&dev, &[]), None);
+    }
+
+    #[test]
+    fn mann_whitney_shares_ranks_between_ties() {
+        // the two 1.1s share rank 2.5, so the tie counts half to each side
+        let result = mann_whitney_u_test(&[1.1, 1.3], &[1.0, 1.1]).unwrap();
+        assert_eq!(result.u, 3.5);
+
+        // nothing but ties is no evidence either way
+        let result = mann_whitney_u_test(&[1.0, 1.0, 1.0], &[1.0, 1.0]).unwrap();
+        assert_eq!(result.u, 3.0);
+        assert_eq!(result.p_value, 1.0);
+
+        // tied samples use the tie-corrected normal approximation: here
+        // mu = 12.5, and the tie groups of 2, 3, and 4 take the variance from
+        // 25/12 * 11 down to 25/12 * (11 - 90/90)
+        let dev = [2.0, 2.0, 3.0, 3.0, 3.0];
+        let baseline = [1.0, 1.0, 2.0, 3.0, 4.0];
+        let result = mann_whitney_u_test(&dev, &baseline).unwrap();
+        assert_eq!(result.u, 15.5);
+        let z = 2.5 / (25.0_f64 / 12.0 * 10.0).sqrt();
+        assert!((result.p_value - erfc(z / std::f64::consts::SQRT_2)).abs() < 1e-12);
+        assert!(result.p_value > 0.05);
+    }
+
+    #[test]
+    fn erfc_matches_known_values() {
+        assert!((erfc(0.0) - 1.0).abs() < 1e-6);
+        assert!((erfc(1.0) - 0.157_299_207).abs() < 1e-6);
+        assert!((erfc(-1.0) - 1.842_700_793).abs() < 1e-6);
+        assert!((erfc(1.959_963_985 / std::f64::consts::SQRT_2) - 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn welch_detects_shifted_mean() {
+        let baseline = [1.0, 1.1, 0.9, 1.05, 0.95];
+        let dev = [1.2, 1.3, 1.1, 1.25, 1.15];
+
+        let result = welch_t_test(&dev, &baseline).unwrap();
+        assert_close(result.t, 4.0);
+        assert_close(result.df, 8.0);
+        assert!(result.p_value < 0.01);
+
+        assert_eq!(welch_t_test(&dev, &[1.0]), None);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::calculate::{Calculation, MetricKey};
+use crate::stats;
+use serde::Serialize;
+
+// The verdict of a whole run, for reading at a glance instead of going
+// through every calculation. Written next to the calculations so CI can act
+// on the totals too.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub compared: usize,
+    // regressions that fail the run
+    pub regressions: usize,
+    // regressions that don't, since they were noisy, failed, suspect,
+    // quarantined, or accepted
+    pub skipped: usize,
+    pub improvements: usize,
+    // too noisy to count toward regressions
+    pub noisy: usize,
+    // pairs with baseline results but none from dev
+    pub missing: usize,
+    // pairs with dev results but no baseline
+    pub new: usize,
+    pub worst: Option<WorstRegression>,
+}
+
+// The regression furthest from its baseline, in baseline standard deviations.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WorstRegression {
+    pub metric: String,
+    pub sigmas: f64,
+}
+
+// Totals up the calculations of a run. How far a regression is from its
+// baseline is measured in the baseline stddev reported by the stddev
+// calculation of the same pair, floored if that's what it was judged by.
+// Stddev regressions, regressions that don't fail the run, and baselines
+// with no spread to measure by, aren't ranked.
+pub fn summarize(calcs: &[Calculation], missing: &[MetricKey], new: &[MetricKey]) -> Summary {
+    let worst = calcs
+        .iter()
+        .filter(|c| c.gates() && !c.metric.starts_with("stddev_"))
+        .filter_map(|c| {
+            let run = c.metric.split_once('_').map_or("", |(_, run)| run);
+            let stddev = calcs
+                .iter()
+                .find(|s| s.metric.strip_prefix("stddev_") == Some(run))?
+                .data
+                .baseline;
+            Some(WorstRegression {
+                metric: c.metric.clone(),
+                // a higher-is-better metric regresses by going down
+                sigmas: stats::safe_ratio((c.data.dev - c.data.baseline).abs(), stddev)?,
+            })
+        })
+        .max_by(|x, y| x.sigmas.total_cmp(&y.sigmas));
+
+    Summary {
+        compared: calcs.len(),
+        regressions: calcs.iter().filter(|c| c.gates()).count(),
+        skipped: calcs.iter().filter(|c| c.regression && !c.gates()).count(),
+        improvements: calcs.iter().filter(|c| c.improvement).count(),
+        noisy: calcs.iter().filter(|c| c.noisy).count(),
+        missing: missing.len(),
+        new: new.len(),
+        worst,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_regressions_by_sigmas() {
+        let calc = |metric: &str, regression, baseline, dev| Calculation {
+            regression,
+            ..Calculation::for_test(metric, baseline, dev)
+        };
+        let mut calcs = vec![
+            // 20% slower, but a noisy baseline
+            calc("median_parse_a.json", true, 1.0, 1.2),
+            calc("stddev_parse_a.json", false, 0.1, 0.1),
+            // 10% slower, with a tight baseline
+            calc("median_parse_b.json", true, 1.0, 1.1),
+            calc("stddev_parse_b.json", true, 0.02, 0.05),
+            // no baseline spread to measure by
+            calc("median_parse_c.json", true, 1.0, 2.0),
+            calc("stddev_parse_c.json", false, 0.0, 0.0),
+            // the worst of all, but accepted
+            calc("median_parse_e.json", true, 1.0, 3.0),
+            calc("stddev_parse_e.json", false, 0.01, 0.01),
+        ];
+        calcs[6].accepted = true;
+        let missing = vec![MetricKey::new("d", "parse")];
+
+        let summary = summarize(&calcs, &missing, &[]);
+        assert_eq!(summary.compared, 8);
+        assert_eq!((summary.regressions, summary.skipped), (4, 1));
+        assert_eq!((summary.missing, summary.new), (1, 0));
+        let worst = summary.worst.unwrap();
+        assert_eq!(worst.metric, "median_parse_b.json");
+        assert!((worst.sigmas - 5.0).abs() < 1e-9);
+
+        assert_eq!(summarize(&calcs[1..2], &[], &[]).worst, None);
+    }
+}